@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE project ADD COLUMN backend VARCHAR
\ No newline at end of file
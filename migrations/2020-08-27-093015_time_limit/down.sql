@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE project DROP COLUMN build_time_limit;
+ALTER TABLE project DROP COLUMN run_time_limit;
+ALTER TABLE project DROP COLUMN build_cpu_limit;
+ALTER TABLE project DROP COLUMN run_cpu_limit;
+ALTER TABLE configuration DROP COLUMN compile_outcome;
+ALTER TABLE configuration DROP COLUMN run_outcome;
+ALTER TABLE grade DROP COLUMN compile_outcome;
+ALTER TABLE grade DROP COLUMN run_outcome;
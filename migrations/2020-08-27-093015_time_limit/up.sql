@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE project ADD COLUMN build_time_limit INTEGER;
+ALTER TABLE project ADD COLUMN run_time_limit INTEGER;
+ALTER TABLE project ADD COLUMN build_cpu_limit INTEGER;
+ALTER TABLE project ADD COLUMN run_cpu_limit INTEGER;
+ALTER TABLE configuration ADD COLUMN compile_outcome VARCHAR;
+ALTER TABLE configuration ADD COLUMN run_outcome VARCHAR;
+ALTER TABLE grade ADD COLUMN compile_outcome VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE grade ADD COLUMN run_outcome VARCHAR NOT NULL DEFAULT '';
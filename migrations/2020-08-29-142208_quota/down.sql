@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE project DROP COLUMN memory_limit;
+ALTER TABLE project DROP COLUMN pids_limit;
+ALTER TABLE project DROP COLUMN disk_limit;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE project ADD COLUMN memory_limit INTEGER;
+ALTER TABLE project ADD COLUMN pids_limit INTEGER;
+ALTER TABLE project ADD COLUMN disk_limit INTEGER;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE project DROP COLUMN display;
\ No newline at end of file
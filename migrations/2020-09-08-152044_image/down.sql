@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE image;
+ALTER TABLE configuration DROP COLUMN image_hash;
+ALTER TABLE grade DROP COLUMN image_hash;
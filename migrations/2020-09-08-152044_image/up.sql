@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE image (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    path VARCHAR UNIQUE NOT NULL,
+    format VARCHAR NOT NULL,
+    hash VARCHAR NOT NULL
+);
+ALTER TABLE configuration ADD COLUMN image_hash VARCHAR;
+ALTER TABLE grade ADD COLUMN image_hash VARCHAR;
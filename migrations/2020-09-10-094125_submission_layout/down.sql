@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE project DROP COLUMN submission_mount;
+ALTER TABLE project DROP COLUMN submission_root;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE project ADD COLUMN submission_mount VARCHAR;
+ALTER TABLE project ADD COLUMN submission_root VARCHAR;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE artifact;
+ALTER TABLE project DROP COLUMN artifacts;
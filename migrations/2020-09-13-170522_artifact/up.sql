@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE artifact (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    grade_id INTEGER,
+    path VARCHAR NOT NULL,
+    location VARCHAR NOT NULL
+);
+ALTER TABLE project ADD COLUMN artifacts VARCHAR;
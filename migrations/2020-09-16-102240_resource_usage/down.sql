@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE configuration DROP COLUMN compile_signal;
+ALTER TABLE configuration DROP COLUMN compile_core_dumped;
+ALTER TABLE configuration DROP COLUMN compile_wall_time;
+ALTER TABLE configuration DROP COLUMN compile_cpu_time;
+ALTER TABLE configuration DROP COLUMN compile_peak_memory;
+ALTER TABLE configuration DROP COLUMN run_signal;
+ALTER TABLE configuration DROP COLUMN run_core_dumped;
+ALTER TABLE configuration DROP COLUMN run_wall_time;
+ALTER TABLE configuration DROP COLUMN run_cpu_time;
+ALTER TABLE configuration DROP COLUMN run_peak_memory;
+ALTER TABLE grade DROP COLUMN compile_signal;
+ALTER TABLE grade DROP COLUMN compile_core_dumped;
+ALTER TABLE grade DROP COLUMN compile_wall_time;
+ALTER TABLE grade DROP COLUMN compile_cpu_time;
+ALTER TABLE grade DROP COLUMN compile_peak_memory;
+ALTER TABLE grade DROP COLUMN run_signal;
+ALTER TABLE grade DROP COLUMN run_core_dumped;
+ALTER TABLE grade DROP COLUMN run_wall_time;
+ALTER TABLE grade DROP COLUMN run_cpu_time;
+ALTER TABLE grade DROP COLUMN run_peak_memory;
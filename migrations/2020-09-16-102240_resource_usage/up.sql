@@ -0,0 +1,21 @@
+-- Your SQL goes here
+ALTER TABLE configuration ADD COLUMN compile_signal INTEGER;
+ALTER TABLE configuration ADD COLUMN compile_core_dumped BOOLEAN;
+ALTER TABLE configuration ADD COLUMN compile_wall_time INTEGER;
+ALTER TABLE configuration ADD COLUMN compile_cpu_time INTEGER;
+ALTER TABLE configuration ADD COLUMN compile_peak_memory INTEGER;
+ALTER TABLE configuration ADD COLUMN run_signal INTEGER;
+ALTER TABLE configuration ADD COLUMN run_core_dumped BOOLEAN;
+ALTER TABLE configuration ADD COLUMN run_wall_time INTEGER;
+ALTER TABLE configuration ADD COLUMN run_cpu_time INTEGER;
+ALTER TABLE configuration ADD COLUMN run_peak_memory INTEGER;
+ALTER TABLE grade ADD COLUMN compile_signal INTEGER;
+ALTER TABLE grade ADD COLUMN compile_core_dumped BOOLEAN;
+ALTER TABLE grade ADD COLUMN compile_wall_time INTEGER;
+ALTER TABLE grade ADD COLUMN compile_cpu_time INTEGER;
+ALTER TABLE grade ADD COLUMN compile_peak_memory INTEGER;
+ALTER TABLE grade ADD COLUMN run_signal INTEGER;
+ALTER TABLE grade ADD COLUMN run_core_dumped BOOLEAN;
+ALTER TABLE grade ADD COLUMN run_wall_time INTEGER;
+ALTER TABLE grade ADD COLUMN run_cpu_time INTEGER;
+ALTER TABLE grade ADD COLUMN run_peak_memory INTEGER;
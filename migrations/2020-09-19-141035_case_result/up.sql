@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE case_result (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    grade_id INTEGER,
+    name VARCHAR NOT NULL,
+    verdict VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    return_code INTEGER,
+    wall_time INTEGER,
+    stdout VARCHAR NOT NULL,
+    stderr VARCHAR NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE project ADD COLUMN comparator VARCHAR;
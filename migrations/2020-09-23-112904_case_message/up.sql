@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE case_result ADD COLUMN message VARCHAR;
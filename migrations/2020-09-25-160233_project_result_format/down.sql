@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE project DROP COLUMN result_format;
+ALTER TABLE project DROP COLUMN test_points;
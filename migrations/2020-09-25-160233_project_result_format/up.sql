@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE project ADD COLUMN result_format VARCHAR;
+ALTER TABLE project ADD COLUMN test_points VARCHAR;
@@ -0,0 +1,83 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE configuration_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    current_student INTEGER,
+    current_project INTEGER,
+    auto_grade INTEGER,
+    manual_grade INTEGER,
+    comment VARCHAR,
+    base_image VARCHAR NOT NULL,
+    compile_stdout VARCHAR,
+    compile_stderr VARCHAR,
+    compile_return INTEGER,
+    run_stdout VARCHAR,
+    run_stderr VARCHAR,
+    run_return INTEGER,
+    compile_outcome VARCHAR,
+    run_outcome VARCHAR,
+    image_hash VARCHAR,
+    compile_signal INTEGER,
+    compile_core_dumped BOOLEAN,
+    compile_wall_time INTEGER,
+    compile_cpu_time INTEGER,
+    compile_peak_memory INTEGER,
+    run_signal INTEGER,
+    run_core_dumped BOOLEAN,
+    run_wall_time INTEGER,
+    run_cpu_time INTEGER,
+    run_peak_memory INTEGER
+);
+INSERT INTO configuration_new (id, current_student, current_project, auto_grade, manual_grade, comment, base_image, compile_stdout, compile_stderr, compile_return, run_stdout, run_stderr, run_return, compile_outcome, run_outcome, image_hash, compile_signal, compile_core_dumped, compile_wall_time, compile_cpu_time, compile_peak_memory, run_signal, run_core_dumped, run_wall_time, run_cpu_time, run_peak_memory)
+    SELECT id, current_student, current_project, CAST(ROUND(auto_grade) AS INTEGER), CAST(ROUND(manual_grade) AS INTEGER), comment, base_image, compile_stdout, compile_stderr, compile_return, run_stdout, run_stderr, run_return, compile_outcome, run_outcome, image_hash, compile_signal, compile_core_dumped, compile_wall_time, compile_cpu_time, compile_peak_memory, run_signal, run_core_dumped, run_wall_time, run_cpu_time, run_peak_memory FROM configuration;
+DROP TABLE configuration;
+ALTER TABLE configuration_new RENAME TO configuration;
+CREATE TABLE grade_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    project_id INTEGER NOT NULL,
+    manual_grade INTEGER NOT NULL DEFAULT 0,
+    auto_grade INTEGER NOT NULL DEFAULT 0,
+    comment VARCHAR NOT NULL DEFAULT '',
+    compile_stdout VARCHAR NOT NULL DEFAULT '',
+    compile_stderr VARCHAR NOT NULL DEFAULT '',
+    compile_return INTEGER NOT NULL DEFAULT 0,
+    run_stdout VARCHAR NOT NULL DEFAULT '',
+    run_stderr VARCHAR NOT NULL DEFAULT '',
+    run_return INTEGER NOT NULL DEFAULT 0,
+    compile_outcome VARCHAR NOT NULL DEFAULT '',
+    run_outcome VARCHAR NOT NULL DEFAULT '',
+    image_hash VARCHAR,
+    compile_signal INTEGER,
+    compile_core_dumped BOOLEAN,
+    compile_wall_time INTEGER,
+    compile_cpu_time INTEGER,
+    compile_peak_memory INTEGER,
+    run_signal INTEGER,
+    run_core_dumped BOOLEAN,
+    run_wall_time INTEGER,
+    run_cpu_time INTEGER,
+    run_peak_memory INTEGER
+);
+INSERT INTO grade_new (id, student_id, project_id, manual_grade, auto_grade, comment, compile_stdout, compile_stderr, compile_return, run_stdout, run_stderr, run_return, compile_outcome, run_outcome, image_hash, compile_signal, compile_core_dumped, compile_wall_time, compile_cpu_time, compile_peak_memory, run_signal, run_core_dumped, run_wall_time, run_cpu_time, run_peak_memory)
+    SELECT id, student_id, project_id, CAST(ROUND(manual_grade) AS INTEGER), CAST(ROUND(auto_grade) AS INTEGER), comment, compile_stdout, compile_stderr, compile_return, run_stdout, run_stderr, run_return, compile_outcome, run_outcome, image_hash, compile_signal, compile_core_dumped, compile_wall_time, compile_cpu_time, compile_peak_memory, run_signal, run_core_dumped, run_wall_time, run_cpu_time, run_peak_memory FROM grade;
+DROP TABLE grade;
+ALTER TABLE grade_new RENAME TO grade;
+CREATE TABLE case_result_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    grade_id INTEGER,
+    name VARCHAR NOT NULL,
+    verdict VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    return_code INTEGER,
+    wall_time INTEGER,
+    stdout VARCHAR NOT NULL,
+    stderr VARCHAR NOT NULL,
+    message VARCHAR
+);
+INSERT INTO case_result_new (id, grade_id, name, verdict, weight, score, return_code, wall_time, stdout, stderr, message)
+    SELECT id, grade_id, name, verdict, CAST(ROUND(weight) AS INTEGER), CAST(ROUND(score) AS INTEGER), return_code, wall_time, stdout, stderr, message FROM case_result;
+DROP TABLE case_result;
+ALTER TABLE case_result_new RENAME TO case_result;
+ALTER TABLE project DROP COLUMN max_auto_grade;
+ALTER TABLE project DROP COLUMN max_manual_grade;
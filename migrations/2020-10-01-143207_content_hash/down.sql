@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE configuration DROP COLUMN submission_hash;
+ALTER TABLE configuration DROP COLUMN template_hash;
+ALTER TABLE grade DROP COLUMN submission_hash;
+ALTER TABLE grade DROP COLUMN template_hash;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE configuration ADD COLUMN submission_hash VARCHAR;
+ALTER TABLE configuration ADD COLUMN template_hash VARCHAR;
+ALTER TABLE grade ADD COLUMN submission_hash VARCHAR;
+ALTER TABLE grade ADD COLUMN template_hash VARCHAR;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE stage_result (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    grade_id INTEGER,
+    position INTEGER NOT NULL,
+    name VARCHAR NOT NULL,
+    outcome VARCHAR NOT NULL,
+    return_code INTEGER,
+    wall_time INTEGER,
+    stdout VARCHAR NOT NULL,
+    stderr VARCHAR NOT NULL,
+    score DOUBLE,
+    max_score DOUBLE
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE rubric_selection;
+DROP TABLE deduction;
+DROP TABLE criterion;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE criterion (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id INTEGER NOT NULL,
+    name VARCHAR NOT NULL,
+    max_points DOUBLE NOT NULL
+);
+CREATE TABLE deduction (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    criterion_id INTEGER NOT NULL,
+    description VARCHAR NOT NULL,
+    points DOUBLE NOT NULL
+);
+CREATE TABLE rubric_selection (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    grade_id INTEGER,
+    criterion_id INTEGER NOT NULL,
+    deduction_id INTEGER
+);
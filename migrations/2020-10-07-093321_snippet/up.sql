@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE snippet (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id INTEGER NOT NULL,
+    key VARCHAR NOT NULL,
+    body VARCHAR NOT NULL,
+    uses INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (project_id, key)
+);
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use diesel::prelude::*;
+use tempfile as tmp;
+
+use crate::container::ContainerBackend;
+use crate::model::{Artifact, ChangeArtifact, Project};
+use crate::schema::artifact::dsl as a;
+
+/// Remove the stored files of `artifacts` together with their rows
+pub fn discard(conn: &SqliteConnection, artifacts: Vec<Artifact>) -> Result<usize> {
+    let mut run_dirs = Vec::new();
+    for i in &artifacts {
+        let location = Path::new(&i.location);
+        if let Err(e) = std::fs::remove_file(location) {
+            log::warn!("failed to remove artifact {}: {}", location.display(), e);
+        }
+        // every run has its own directory, `location` mirrors `path` below it
+        let depth = Path::new(&i.path).components().count() - 1;
+        if let Some(x) = location.ancestors().nth(depth) {
+            if !run_dirs.contains(&x.to_path_buf()) {
+                run_dirs.push(x.to_path_buf());
+            }
+        }
+    }
+    for i in run_dirs.iter().filter(|x| x.is_dir()) {
+        if let Err(e) = std::fs::remove_dir_all(i) {
+            log::warn!("failed to remove {}: {}", i.display(), e);
+        }
+    }
+    diesel::delete(a::artifact.filter(a::id.eq_any(artifacts.iter().map(|x| x.id).collect::<Vec<_>>())))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Drop the artifacts of the current, uncommitted result
+pub fn clean(conn: &SqliteConnection) -> Result<usize> {
+    a::artifact
+        .filter(a::grade_id.is_null())
+        .load::<Artifact>(conn)
+        .map_err(Into::into)
+        .and_then(|x| discard(conn, x))
+}
+
+/// Drop the artifacts of a committed grade, or of all grades if `grade_id` is not given
+pub fn remove(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<usize> {
+    let artifacts = match grade_id {
+        Some(id) => a::artifact.filter(a::grade_id.eq(id)).load::<Artifact>(conn)?,
+        None => a::artifact.filter(a::grade_id.is_not_null()).load::<Artifact>(conn)?,
+    };
+    discard(conn, artifacts)
+}
+
+/// Attach the current artifacts to `grade_id`, replacing the ones it had before
+pub fn commit(conn: &SqliteConnection, grade_id: i32) -> Result<usize> {
+    let old = a::artifact
+        .filter(a::grade_id.eq(grade_id))
+        .load::<Artifact>(conn)?;
+    discard(conn, old)?;
+    diesel::update(a::artifact.filter(a::grade_id.is_null()))
+        .set(a::grade_id.eq(grade_id))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Container paths the artifact globs of a project apply to, relative ones start at `/project`
+fn patterns(globs: &str) -> Result<Vec<glob::Pattern>> {
+    globs.split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| if x.starts_with('/') { x.to_string() } else { format!("/project/{}", x) })
+        .map(|x| glob::Pattern::new(&x).map_err(|e| anyhow!("invalid artifact glob {}: {}", x, e)))
+        .collect()
+}
+
+/// Copy the files matching the artifact globs of `project` out of the writable layers into
+/// `artifacts/<project>/<student>-<random>`, staging them in `scratch`. Returns the container
+/// path and the stored location of every file.
+pub fn collect(container: &dyn ContainerBackend,
+               project: &Project,
+               mount: &Path,
+               student_id: i32,
+               scratch: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let patterns = match &project.artifacts {
+        Some(x) => patterns(x)?,
+        None => return Ok(Vec::new())
+    };
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let staging = tmp::TempDir::new_in(scratch)?;
+    container.save_upper(staging.path())?;
+    // later layers shadow the earlier ones
+    let mut files: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for (name, prefix) in &[("root", Path::new("/")), ("project", Path::new("/project")), ("submission", mount)] {
+        let layer = staging.path().join(name);
+        if !layer.is_dir() {
+            continue;
+        }
+        for entry in walkdir::WalkDir::new(&layer) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = prefix.join(entry.path().strip_prefix(&layer)?);
+            if patterns.iter().any(|x| x.matches_path_with(&path, options)) {
+                files.insert(path, entry.path().to_path_buf());
+            }
+        }
+    }
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+    let project_dir = std::env::current_dir()?.join("artifacts").join(&project.name);
+    std::fs::create_dir_all(&project_dir)?;
+    let run_dir = tmp::Builder::new()
+        .prefix(&format!("{}-", student_id))
+        .tempdir_in(&project_dir)?
+        .into_path();
+    let mut collected = Vec::new();
+    for (path, source) in files {
+        let location = run_dir.join(path.strip_prefix("/")?);
+        log::debug!("capturing artifact {} to {}", path.display(), location.display());
+        std::fs::create_dir_all(location.parent().unwrap_or(&run_dir))?;
+        std::fs::copy(source, &location)?;
+        collected.push((path, location));
+    }
+    Ok(collected)
+}
+
+/// Replace the current artifacts with files returned by `collect`
+pub fn record(conn: &SqliteConnection, files: &[(PathBuf, PathBuf)]) -> Result<usize> {
+    clean(conn)?;
+    for (path, location) in files {
+        diesel::insert_into(a::artifact)
+            .values(ChangeArtifact {
+                grade_id: None,
+                path: path.to_str(),
+                location: location.to_str(),
+            })
+            .execute(conn)?;
+    }
+    Ok(files.len())
+}
+
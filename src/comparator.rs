@@ -0,0 +1,189 @@
+use anyhow::*;
+
+/// How the output of a test case is checked against its expected output
+///
+/// - `exact`: byte for byte, the default
+/// - `whitespace`: ignore trailing whitespace on every line and blank lines
+/// - `float[:abs=EPS][,rel=EPS]`: compare whitespace separated tokens, numbers within either
+///   tolerance are equal, both default to `1e-6`
+/// - `regex`: every expected line is a regex the whole output line must match
+/// - `checker:PATH`: run `sh PATH <input> <expected>` inside the container with the output
+///   on stdin, its last line of stdout is the fraction of the weight in `[0, 1]`; like the
+///   other scripts it runs in `/project`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comparator {
+    Exact,
+    Whitespace,
+    Float { abs: f64, rel: f64 },
+    Regex,
+    Checker(String),
+}
+
+pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+
+impl std::str::FromStr for Comparator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("").trim();
+        let argument = parts.next().map(str::trim);
+        match (kind, argument) {
+            ("exact", None) => Ok(Comparator::Exact),
+            ("whitespace", None) => Ok(Comparator::Whitespace),
+            ("regex", None) => Ok(Comparator::Regex),
+            ("float", argument) => {
+                let mut abs = DEFAULT_TOLERANCE;
+                let mut rel = DEFAULT_TOLERANCE;
+                for option in argument.unwrap_or("").split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                    let mut pair = option.splitn(2, '=');
+                    let target = match pair.next() {
+                        Some("abs") => &mut abs,
+                        Some("rel") => &mut rel,
+                        _ => return Err(anyhow!("unknown float tolerance {}, expected abs or rel", option))
+                    };
+                    *target = pair.next()
+                        .ok_or(anyhow!("missing value of {}", option))?
+                        .parse()
+                        .map_err(|e| anyhow!("invalid tolerance {}: {}", option, e))?;
+                    if target.is_nan() || *target < 0.0 {
+                        return Err(anyhow!("tolerance must not be negative: {}", option));
+                    }
+                }
+                Ok(Comparator::Float { abs, rel })
+            }
+            ("checker", Some(path)) if !path.is_empty() => Ok(Comparator::Checker(path.to_string())),
+            _ => Err(anyhow!("invalid comparator {}, expected one of exact, whitespace, \
+            float[:abs=EPS][,rel=EPS], regex, checker:PATH", s))
+        }
+    }
+}
+
+impl std::fmt::Display for Comparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Comparator::Exact => write!(f, "exact"),
+            Comparator::Whitespace => write!(f, "whitespace"),
+            Comparator::Float { abs, rel } => write!(f, "float:abs={},rel={}", abs, rel),
+            Comparator::Regex => write!(f, "regex"),
+            Comparator::Checker(path) => write!(f, "checker:{}", path),
+        }
+    }
+}
+
+fn significant_lines(x: &str) -> impl Iterator<Item=&str> {
+    x.lines().map(str::trim_end).filter(|x| !x.is_empty())
+}
+
+fn float_equal(output: &str, expected: &str, abs: f64, rel: f64) -> bool {
+    match (output.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(x), Ok(y)) if x.is_finite() && y.is_finite() => {
+            let diff = (x - y).abs();
+            diff <= abs || diff <= rel * y.abs()
+        }
+        _ => output == expected
+    }
+}
+
+fn regex_match(output: &str, expected: &str) -> Result<bool> {
+    let output = output.lines().collect::<Vec<_>>();
+    let expected = expected.lines().collect::<Vec<_>>();
+    if output.len() != expected.len() {
+        return Ok(false);
+    }
+    for (x, pattern) in output.iter().zip(&expected) {
+        let regex = regex::Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| anyhow!("invalid expected regex {}: {}", pattern, e))?;
+        if !regex.is_match(x) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+impl Comparator {
+    /// Whether `output` matches `expected`, a checker must be run by the caller instead
+    pub fn accepts(&self, output: &str, expected: &str) -> Result<bool> {
+        match self {
+            Comparator::Exact => Ok(output == expected),
+            Comparator::Whitespace => Ok(significant_lines(output).eq(significant_lines(expected))),
+            Comparator::Float { abs, rel } => {
+                let output = output.split_whitespace().collect::<Vec<_>>();
+                let expected = expected.split_whitespace().collect::<Vec<_>>();
+                Ok(output.len() == expected.len()
+                    && output.iter().zip(&expected).all(|(x, y)| float_equal(x, y, *abs, *rel)))
+            }
+            Comparator::Regex => regex_match(output, expected),
+            Comparator::Checker(_) => Err(anyhow!("a checker can only run inside the container"))
+        }
+    }
+
+    pub fn checker(&self) -> Option<&str> {
+        match self {
+            Comparator::Checker(path) => Some(path),
+            _ => None
+        }
+    }
+}
+
+/// The fraction printed on the last line of a checker's stdout
+pub fn parse_checker_score(stdout: &str) -> Result<f64> {
+    let line = stdout.lines()
+        .rev()
+        .map(str::trim)
+        .find(|x| !x.is_empty())
+        .ok_or(anyhow!("checker printed nothing"))?;
+    let score: f64 = line.parse()
+        .map_err(|e| anyhow!("invalid checker score {}: {}", line, e))?;
+    if (0.0..=1.0).contains(&score) {
+        Ok(score)
+    } else {
+        Err(anyhow!("checker score {} is out of [0, 1]", score))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!("exact".parse::<Comparator>()?, Comparator::Exact);
+        assert_eq!("float".parse::<Comparator>()?, Comparator::Float { abs: 1e-6, rel: 1e-6 });
+        assert_eq!("float:rel=0.01".parse::<Comparator>()?, Comparator::Float { abs: 1e-6, rel: 0.01 });
+        assert_eq!("float:abs=0.5,rel=0".parse::<Comparator>()?, Comparator::Float { abs: 0.5, rel: 0.0 });
+        assert_eq!("checker:check.sh".parse::<Comparator>()?.checker(), Some("check.sh"));
+        for i in &["float:abs=-1", "float:eps=1", "checker", "checker:", "exact:1", "diff"] {
+            assert!(i.parse::<Comparator>().is_err(), "{}", i);
+        }
+        let float: Comparator = "float:abs=0.5".parse()?;
+        assert_eq!(float.to_string().parse::<Comparator>()?, float);
+        Ok(())
+    }
+
+    #[test]
+    fn test_accepts() -> Result<()> {
+        assert!(Comparator::Exact.accepts("1\n", "1\n")?);
+        assert!(!Comparator::Exact.accepts("1 \n", "1\n")?);
+        assert!(Comparator::Whitespace.accepts("1  \n\n2\n\n", "1\n2")?);
+        assert!(!Comparator::Whitespace.accepts("1 2\n", "1  2\n")?);
+        let float: Comparator = "float:abs=0.01,rel=0".parse()?;
+        assert!(float.accepts("x 1.005\n", "x\t1\n")?);
+        assert!(!float.accepts("x 1.02", "x 1")?);
+        assert!(!float.accepts("y 1", "x 1")?);
+        assert!(float.accepts("1 nan", "1 nan")?);
+        assert!(Comparator::Regex.accepts("took 12ms\nok\n", "took \\d+ms\nok")?);
+        assert!(!Comparator::Regex.accepts("took 12ms!\nok\n", "took \\d+ms\nok")?);
+        assert!(!Comparator::Regex.accepts("ok\n", "ok\nok")?);
+        assert!(Comparator::Regex.accepts("ok", "(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checker_score() {
+        assert_eq!(parse_checker_score("partial\n0.5\n\n").ok(), Some(0.5));
+        assert!(parse_checker_score("").is_err());
+        assert!(parse_checker_score("1.5").is_err());
+        assert!(parse_checker_score("ok").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::*;
+use tempfile as tmp;
+
+use crate::utils::AndThenInto;
+
+use super::*;
+use super::registry::{Entry, Record};
+
+/// Unprivileged backend built on `bwrap`. A squashfs image is mounted with `squashfuse` on the host and
+/// every command starts a fresh sandbox that stacks overlays on top of it; the upper dirs are
+/// shared between commands so build results survive until `run.sh`.
+/// Requires bubblewrap with `--overlay` support (0.10+) and unprivileged overlayfs (Linux 5.11+).
+pub struct BwrapContainer {
+    /// mount point of a squashfs image
+    image_dir: Option<tmp::TempDir>,
+    lower_dir: std::path::PathBuf,
+    x11: Option<X11>,
+    project_dir: std::path::PathBuf,
+    student_dir: std::path::PathBuf,
+    mount: std::path::PathBuf,
+    root_mount_pair: (tmp::TempDir, tmp::TempDir),
+    project_mount_pair: (tmp::TempDir, tmp::TempDir),
+    student_mount_pair: (tmp::TempDir, tmp::TempDir),
+    quota: Quota,
+    _entry: Entry,
+}
+
+impl BwrapContainer {
+    fn scratch_pair(scratch: &Path, entry: &mut Entry) -> Result<(tmp::TempDir, tmp::TempDir)> {
+        let pair = (tmp::TempDir::new_in(scratch)?, tmp::TempDir::new_in(scratch)?);
+        entry.add_dir(pair.0.path())?;
+        entry.add_dir(pair.1.path())?;
+        Ok(pair)
+    }
+    pub fn new(spec: &Spec) -> Result<Self> {
+        let (submission_dir, project_dir) = (spec.submission, spec.project);
+        let mut entry = Entry::new(Record { backend: Backend::Bwrap.to_string(), ..Default::default() })?;
+        let (image_dir, lower_dir) = match spec.image {
+            RootFs::Squashfs(image_path) => {
+                let image_dir = tmp::TempDir::new_in(spec.scratch)?;
+                entry.add_dir(image_dir.path())?;
+                entry.add_mount(image_dir.path())?;
+                log::debug!("mounting squashfs with squashfuse, image={}, target={}", image_path.display(),
+                            image_dir.path().display());
+                Command::new("squashfuse")
+                    .arg(image_path)
+                    .arg(image_dir.path())
+                    .spawn()?
+                    .wait_success()?;
+                let lower_dir = image_dir.path().to_path_buf();
+                (Some(image_dir), lower_dir)
+            }
+            RootFs::Directory(path) => (None, path.clone())
+        };
+        let container = BwrapContainer {
+            x11: X11::new(spec.display)
+                .and_then(|x11| entry.add_display(x11.as_ref()).map(|_| x11))
+                .map_err(|e| {
+                    if let Some(x) = &image_dir {
+                        BwrapContainer::clean_up(x.path());
+                    }
+                    e
+                })?,
+            image_dir,
+            lower_dir,
+            project_dir: project_dir.canonicalize()?,
+            student_dir: submission_dir.canonicalize()?,
+            mount: spec.mount.to_path_buf(),
+            root_mount_pair: Self::scratch_pair(spec.scratch, &mut entry)?,
+            project_mount_pair: Self::scratch_pair(spec.scratch, &mut entry)?,
+            student_mount_pair: Self::scratch_pair(spec.scratch, &mut entry)?,
+            quota: spec.quota,
+            _entry: entry,
+        };
+        Ok(container)
+    }
+    pub fn clean_up(path: &Path) {
+        log::info!("cleaning up bwrap container");
+        match Command::new("fusermount")
+            .arg("-u")
+            .arg(path)
+            .spawn()
+            .and_then_into(|mut x| x.wait_success()) {
+            Ok(()) => (),
+            Err(e) => log::error!("{}", e)
+        }
+    }
+}
+
+impl Drop for BwrapContainer {
+    fn drop(&mut self) {
+        if let Some(x) = &self.image_dir {
+            BwrapContainer::clean_up(x.path());
+        }
+        // overlayfs leaves a mode 000 directory inside each work dir
+        for i in &[&self.root_mount_pair, &self.project_mount_pair, &self.student_mount_pair] {
+            match Command::new("chmod")
+                .arg("-R")
+                .arg("u+rwX")
+                .arg(i.0.path())
+                .arg(i.1.path())
+                .spawn()
+                .and_then_into(|mut x| x.wait_success()) {
+                Ok(()) => (),
+                Err(e) => log::error!("failed to reset permissions: {}", e)
+            }
+        }
+    }
+}
+
+impl ContainerBackend for BwrapContainer {
+    fn cmd(&self) -> Result<Command> {
+        let mut command = self.quota.command("bwrap");
+        command
+            .arg("--die-with-parent")
+            .arg("--unshare-all")
+            .arg("--share-net")
+            .arg("--uid")
+            .arg("0")
+            .arg("--gid")
+            .arg("0");
+        for (source, (upper, work), target) in &[
+            (self.lower_dir.as_path(), &self.root_mount_pair, Path::new("/")),
+            (self.project_dir.as_path(), &self.project_mount_pair, Path::new("/project")),
+            (self.student_dir.as_path(), &self.student_mount_pair, self.mount.as_path()),
+        ] {
+            command
+                .arg("--overlay-src")
+                .arg(source)
+                .arg("--overlay")
+                .arg(upper.path())
+                .arg(work.path())
+                .arg(target);
+        }
+        command
+            .arg("--dev")
+            .arg("/dev")
+            .arg("--proc")
+            .arg("/proc")
+            .arg("--tmpfs")
+            .arg("/tmp");
+        if let Some(x11) = &self.x11 {
+            command
+                .arg("--ro-bind")
+                .arg("/tmp/.X11-unix")
+                .arg("/tmp/.X11-unix")
+                .arg("--ro-bind")
+                .arg(x11.magic.path())
+                .arg(x11.magic.path());
+            for (key, value) in x11.env() {
+                command.arg("--setenv").arg(key).arg(value);
+            }
+        }
+        command
+            .arg("--chdir")
+            .arg("/project");
+        Ok(command)
+    }
+    fn upper_dirs(&self) -> Vec<(&'static str, std::path::PathBuf)> {
+        vec![("root", self.root_mount_pair.0.path().to_path_buf()),
+             ("project", self.project_mount_pair.0.path().to_path_buf()),
+             ("submission", self.student_mount_pair.0.path().to_path_buf())]
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::*;
+use tempfile as tmp;
+
+use super::*;
+use super::registry::{Entry, Record};
+
+/// No isolation at all: the project template and the submission are copied into a temporary
+/// directory and the scripts run there directly on the host. Only meant for trusted
+/// submissions, tests, and machines without any container runtime.
+pub struct HostContainer {
+    root_dir: tmp::TempDir,
+    x11: Option<X11>,
+    quota: Quota,
+    _entry: Entry,
+}
+
+impl HostContainer {
+    fn copy(source: &Path, target: &Path) -> Result<()> {
+        log::debug!("copying {} to {}", source.display(), target.display());
+        std::fs::create_dir_all(target)?;
+        Command::new("cp")
+            .arg("-a")
+            .arg("--")
+            .arg(source.join("."))
+            .arg(target)
+            .spawn()?
+            .wait_success()
+    }
+    pub fn new(spec: &Spec) -> Result<Self> {
+        let mut entry = Entry::new(Record { backend: Backend::Host.to_string(), ..Default::default() })?;
+        let root_dir = tmp::TempDir::new_in(spec.scratch)?;
+        entry.add_dir(root_dir.path())?;
+        Self::copy(spec.project, root_dir.path())?;
+        // the copy of the project plays the role of `/project`, there is nothing outside of it
+        let target = spec.mount
+            .strip_prefix("/project")
+            .map_err(|_| anyhow!("the host backend cannot mount the submission at {}", spec.mount.display()))?;
+        Self::copy(spec.submission, &root_dir.path().join(target))?;
+        let x11 = X11::new(spec.display)?;
+        entry.add_display(x11.as_ref())?;
+        Ok(HostContainer {
+            root_dir,
+            x11,
+            quota: spec.quota,
+            _entry: entry,
+        })
+    }
+}
+
+impl ContainerBackend for HostContainer {
+    fn cmd(&self) -> Result<Command> {
+        let mut command = self.quota.command("env");
+        command.current_dir(self.root_dir.path());
+        match &self.x11 {
+            Some(x11) => command.envs(x11.env()),
+            None => command.env_remove("DISPLAY"),
+        };
+        Ok(command)
+    }
+    /// everything is writable, the whole copy of the project is the only layer
+    fn upper_dirs(&self) -> Vec<(&'static str, std::path::PathBuf)> {
+        vec![("project", self.root_dir.path().to_path_buf())]
+    }
+}
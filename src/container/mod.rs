@@ -0,0 +1,377 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::*;
+use tempfile as tmp;
+
+use crate::image::RootFs;
+
+pub use bwrap::BwrapContainer;
+pub use host::HostContainer;
+pub use nspawn::NspawnContainer;
+pub use registry::{recover, worker_dir};
+pub use rootless::RootlessContainer;
+
+mod nspawn;
+mod rootless;
+mod bwrap;
+mod host;
+mod registry;
+
+pub(crate) trait WaitSuccess {
+    fn wait_success(&mut self) -> Result<()>;
+}
+
+impl WaitSuccess for std::process::Child {
+    fn wait_success(&mut self) -> Result<(), Error> {
+        self.wait()
+            .map_err(Into::into)
+            .and_then(|x| if x.success() {
+                Ok(())
+            } else {
+                Err(anyhow!("exit with {}", x.code().map(|x|x.to_string()).unwrap_or_else(String::new)))
+            })
+    }
+}
+
+fn create_magic(display: &str) -> Result<tmp::NamedTempFile> {
+    let file = tmp::NamedTempFile::new()?;
+    Command::new("sh")
+        .arg("-c")
+        .arg(format!("xauth nextract - \"{}\" | sed -e 's/^..../ffff/' | xauth -f \"{}\" nmerge -", display,
+                     file.path().to_str().ok_or(anyhow!("file path initialized error"))?))
+        .spawn()?
+        .wait_success()?;
+    Ok(file)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisplayMode {
+    /// console project, no X11 at all
+    None,
+    /// forward `$DISPLAY` of the grader
+    Host,
+    /// a private Xvfb server per container
+    Xvfb,
+}
+
+pub const DISPLAY_MODES: &[&str] = &["none", "host", "xvfb"];
+
+impl DisplayMode {
+    /// forward the display of the grader if there is one
+    pub fn auto() -> Self {
+        if std::env::var_os("DISPLAY").is_some() {
+            DisplayMode::Host
+        } else {
+            DisplayMode::None
+        }
+    }
+}
+
+impl std::str::FromStr for DisplayMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(DisplayMode::None),
+            "host" => Ok(DisplayMode::Host),
+            "xvfb" => Ok(DisplayMode::Xvfb),
+            _ => Err(anyhow!("unknown display mode: {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DisplayMode::None => "none",
+            DisplayMode::Host => "host",
+            DisplayMode::Xvfb => "xvfb",
+        })
+    }
+}
+
+/// An X11 display the container may connect to through `/tmp/.X11-unix`
+pub struct X11 {
+    display: String,
+    magic: tmp::NamedTempFile,
+    server: Option<std::process::Child>,
+}
+
+impl X11 {
+    pub fn new(mode: DisplayMode) -> Result<Option<Self>> {
+        match mode {
+            DisplayMode::None => Ok(None),
+            DisplayMode::Host => {
+                let display = std::env::var("DISPLAY")?;
+                let magic = create_magic(&display)?;
+                Ok(Some(X11 { display, magic, server: None }))
+            }
+            DisplayMode::Xvfb => (99..199)
+                .filter(|x| !Path::new(&format!("/tmp/.X11-unix/X{}", x)).exists()
+                    && !Path::new(&format!("/tmp/.X{}-lock", x)).exists())
+                .take(5)
+                .find_map(|x| match Self::start_xvfb(x) {
+                    Ok(x) => Some(x),
+                    Err(e) => {
+                        log::warn!("failed to start Xvfb on :{}: {}", x, e);
+                        None
+                    }
+                })
+                .map(Some)
+                .ok_or(anyhow!("no usable display for Xvfb")),
+        }
+    }
+    fn start_xvfb(number: u32) -> Result<Self> {
+        let display = format!(":{}", number);
+        let magic = tmp::NamedTempFile::new()?;
+        let path = magic.path().to_str().ok_or(anyhow!("file path initialized error"))?;
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("xauth -f \"{path}\" add {display} . $(mcookie) && \
+                          xauth -f \"{path}\" nlist {display} | sed -e 's/^..../ffff/' | xauth -f \"{path}\" nmerge -",
+                         path = path, display = display))
+            .spawn()?
+            .wait_success()?;
+        log::debug!("starting Xvfb on {}", display);
+        let mut server = Command::new("Xvfb")
+            .arg(&display)
+            .arg("-auth")
+            .arg(magic.path())
+            .arg("-nolisten")
+            .arg("tcp")
+            .arg("-screen")
+            .arg("0")
+            .arg("1280x1024x24")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        let socket = format!("/tmp/.X11-unix/X{}", number);
+        for _ in 0..50 {
+            if let Some(status) = server.try_wait()? {
+                return Err(anyhow!("Xvfb exited with {}", status));
+            }
+            if Path::new(&socket).exists() {
+                return Ok(X11 { display, magic, server: Some(server) });
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        let _ = server.kill();
+        let _ = server.wait();
+        Err(anyhow!("Xvfb did not come up"))
+    }
+    /// the environment variables a client needs
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![("DISPLAY", self.display.clone()),
+             ("XAUTHORITY", self.magic.path().display().to_string())]
+    }
+}
+
+impl Drop for X11 {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.as_mut() {
+            log::debug!("stopping Xvfb on {}", self.display);
+            if let Err(e) = server.kill().and_then(|_| server.wait()) {
+                log::error!("failed to stop Xvfb: {}", e);
+            }
+        }
+    }
+}
+
+pub(crate) fn quote<P: AsRef<Path>>(path: P) -> Result<String> {
+    path.as_ref()
+        .to_str()
+        .ok_or(anyhow!("invalid path {}", path.as_ref().display()))
+        .map(|x| format!("'{}'", x.replace('\'', "'\\''")))
+}
+
+/// `cp -a` through `command`, overlay whiteouts are device nodes that may fail to copy
+fn copy_layer(mut command: Command, source: &Path, target: &Path) -> Result<()> {
+    std::fs::create_dir_all(target)?;
+    if let Err(e) = command
+        .arg("-a")
+        .arg("--")
+        .arg(source.join("."))
+        .arg(target)
+        .spawn()?
+        .wait_success() {
+        log::warn!("some files of {} could not be copied: {}", source.display(), e);
+    }
+    Ok(())
+}
+
+/// Resource limits of a container, `None` means unlimited
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Quota {
+    /// memory limit in MiB, swap is disabled when set
+    pub memory: Option<u64>,
+    /// maximum number of processes and threads
+    pub pids: Option<u64>,
+    /// size limit of all writable layers in MiB
+    pub disk: Option<u64>,
+}
+
+impl Quota {
+    pub fn new(memory: Option<i32>, pids: Option<i32>, disk: Option<i32>) -> Self {
+        Quota {
+            memory: memory.map(|x| x as u64),
+            pids: pids.map(|x| x as u64),
+            disk: disk.map(|x| x as u64),
+        }
+    }
+    /// systemd resource control properties enforcing the memory and process limits
+    fn properties(&self) -> Vec<String> {
+        let mut properties = Vec::new();
+        if let Some(memory) = self.memory {
+            properties.push(format!("MemoryMax={}M", memory));
+            properties.push(String::from("MemorySwapMax=0"));
+        }
+        if let Some(pids) = self.pids {
+            properties.push(format!("TasksMax={}", pids));
+        }
+        properties
+    }
+    /// `program` wrapped in a transient systemd scope of the user if there is anything to limit
+    fn command(&self, program: &str) -> Command {
+        let properties = self.properties();
+        if properties.is_empty() {
+            return Command::new(program);
+        }
+        let mut command = Command::new("systemd-run");
+        command
+            .arg("--user")
+            .arg("--scope")
+            .arg("--quiet");
+        for i in properties {
+            command.arg("-p").arg(i);
+        }
+        command.arg(program);
+        command
+    }
+}
+
+/// Everything a backend needs to know to build a container
+pub struct Spec<'a> {
+    pub image: &'a RootFs,
+    /// root of the student submission on the host
+    pub submission: &'a Path,
+    /// absolute path inside the container the submission is mounted at
+    pub mount: &'a Path,
+    pub project: &'a Path,
+    pub quota: Quota,
+    pub display: DisplayMode,
+    /// where the temporary mount points and writable layers are created, one per worker
+    pub scratch: &'a Path,
+}
+
+impl<'a> Spec<'a> {
+    /// where the submission has to be mounted when the container root is at `root`
+    fn mount_in(&self, root: &Path) -> std::path::PathBuf {
+        root.join(self.mount.strip_prefix("/").unwrap_or(self.mount))
+    }
+}
+
+/// A prepared grading environment: the root image with the project template at `/project` and
+/// the student submission at `Spec::mount`, `/project/src` by default. Writes land in per-container scratch space and are
+/// discarded when the value is dropped.
+pub trait ContainerBackend {
+    /// A command that runs its remaining arguments at `/project` inside the container
+    fn cmd(&self) -> Result<Command>;
+    /// Host paths of the writable layers, named `root`, `project` and `submission`
+    fn upper_dirs(&self) -> Vec<(&'static str, std::path::PathBuf)>;
+    /// Copy the writable layers into `target`, one directory per layer
+    fn save_upper(&self, target: &Path) -> Result<()> {
+        for (name, path) in self.upper_dirs() {
+            copy_layer(Command::new("cp"), &path, &target.join(name))?;
+        }
+        Ok(())
+    }
+    /// Start a command created by `cmd` so that `terminate` can reach all of its descendants
+    fn spawn(&self, command: &mut Command) -> Result<std::process::Child> {
+        use std::os::unix::process::CommandExt;
+        command
+            .process_group(0)
+            .spawn()
+            .map_err(Into::into)
+    }
+    /// Kill a command started with `spawn` together with everything it forked
+    fn terminate(&self, child: &mut std::process::Child) -> Result<()> {
+        Command::new("kill")
+            .arg("-s")
+            .arg("KILL")
+            .arg("--")
+            .arg(format!("-{}", child.id()))
+            .spawn()?
+            .wait_success()
+    }
+    /// Used when a command is still alive a while after `terminate`, must not be ignorable
+    fn kill(&self, child: &mut std::process::Child) -> Result<()> {
+        self.terminate(child)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// `sudo mount` + `systemd-nspawn`, needs root on the grading machine
+    Nspawn,
+    /// `squashfuse` + overlayfs inside an unprivileged user/mount namespace
+    Rootless,
+    /// `squashfuse` + `bwrap --overlay`, unprivileged
+    Bwrap,
+    /// no sandbox at all, scripts run on the host in a temporary copy of the project
+    Host,
+}
+
+pub const BACKENDS: &[&str] = &["nspawn", "rootless", "bwrap", "host"];
+
+impl Backend {
+    pub fn create(&self, spec: &Spec) -> Result<Box<dyn ContainerBackend>> {
+        log::debug!("creating {} container with {:?}, display={}", self, spec.quota, spec.display);
+        if spec.quota.disk.is_some() && (*self == Backend::Bwrap || *self == Backend::Host) {
+            log::warn!("disk quota is not supported by the {} backend", self);
+        }
+        Ok(match self {
+            Backend::Nspawn => Box::new(NspawnContainer::new(spec)?),
+            Backend::Rootless => Box::new(RootlessContainer::new(spec)?),
+            Backend::Bwrap => Box::new(BwrapContainer::new(spec)?),
+            Backend::Host => Box::new(HostContainer::new(spec)?),
+        })
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nspawn" => Ok(Backend::Nspawn),
+            "rootless" => Ok(Backend::Rootless),
+            "bwrap" => Ok(Backend::Bwrap),
+            "host" => Ok(Backend::Host),
+            _ => Err(anyhow!("unknown backend: {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Backend::Nspawn => "nspawn",
+            Backend::Rootless => "rootless",
+            Backend::Bwrap => "bwrap",
+            Backend::Host => "host",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_magic() -> Result<()> {
+        let file = create_magic(&std::env::var("DISPLAY")?)?;
+        let content = std::fs::read(file.path())?;
+        Ok(println!("{}", String::from_utf8_lossy(content.as_slice())))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,298 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::*;
+use tempfile as tmp;
+
+use crate::utils::AndThenInto;
+
+use super::*;
+use super::registry::{Entry, Record};
+
+/// Directory holding the upper and work dirs of the overlays.
+/// With a disk quota it is a size limited tmpfs, unmounted again on drop.
+struct Scratch {
+    dir: tmp::TempDir,
+    mounted: bool,
+}
+
+impl Scratch {
+    fn new(quota: &Quota, scratch: &Path, entry: &mut Entry) -> Result<Self> {
+        use std::os::unix::fs::MetadataExt;
+        let dir = tmp::TempDir::new_in(scratch)?;
+        entry.add_dir(dir.path())?;
+        let mounted = quota.disk.is_some();
+        if let Some(disk) = quota.disk {
+            entry.add_mount(dir.path())?;
+            let metadata = dir.path().metadata()?;
+            log::debug!("mounting tmpfs, size={}M, target={}", disk, dir.path().display());
+            Command::new("sudo")
+                .arg("mount")
+                .arg("-t")
+                .arg("tmpfs")
+                .arg("-o")
+                .arg(format!("size={}m,uid={},gid={},mode=0700", disk, metadata.uid(), metadata.gid()))
+                .arg("tmpfs")
+                .arg(dir.path())
+                .spawn()?
+                .wait_success()?;
+        }
+        Ok(Scratch { dir, mounted })
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if self.mounted {
+            match Command::new("sudo")
+                .arg("umount")
+                .arg(self.dir.path())
+                .spawn()
+                .and_then_into(|mut x| x.wait_success()) {
+                Ok(()) => (),
+                Err(e) => log::error!("failed to unmount tmpfs: {}", e)
+            }
+        }
+    }
+}
+
+pub struct NspawnContainer {
+    lower_dir: tmp::TempDir,
+    /// name the container is registered with, `machinectl` finds it by this
+    machine: String,
+    depth: usize,
+    x11: Option<X11>,
+    quota: Quota,
+    _root_mount_pair: (tmp::TempDir, tmp::TempDir),
+    _project_mount_pair: (tmp::TempDir, tmp::TempDir),
+    _student_mount_pair: (tmp::TempDir, tmp::TempDir),
+    _entry: Entry,
+    // declared last, it must outlive the overlays
+    _scratch: Scratch,
+}
+
+impl NspawnContainer {
+    fn overlay<A, B>(scratch: &Scratch, base_path: A, target_path: B) -> Result<(tmp::TempDir, tmp::TempDir)>
+        where A: AsRef<Path>, B: AsRef<Path> {
+        let upper_dir = tmp::TempDir::new_in(scratch.dir.path())?;
+        let work_dir = tmp::TempDir::new_in(scratch.dir.path())?;
+        log::debug!("mounting overlay: lowerdir={},upperdir={},workdir={},target={}", base_path.as_ref().display(),
+            upper_dir.path().display(), work_dir.path().display(), target_path.as_ref().display());
+        Command::new("sudo")
+            .arg("mount")
+            .arg("-t")
+            .arg("overlay")
+            .arg("-o")
+            .arg(format!(
+                "lowerdir={},upperdir={},workdir={}",
+                base_path.as_ref().to_str().ok_or(anyhow!("invalid lower_dir"))?,
+                upper_dir.path().to_str().ok_or(anyhow!("invalid upper_dir"))?,
+                work_dir.path().to_str().ok_or(anyhow!("invalid work_dir"))?,
+            ))
+            .arg("overlay")
+            .arg(target_path.as_ref())
+            .spawn()?
+            .wait_success()?;
+        Ok((upper_dir, work_dir))
+    }
+    pub fn new(spec: &Spec) -> Result<Self> {
+        let (submission_dir, project_dir) = (spec.submission, spec.project);
+        let mut entry = Entry::new(Record {
+            backend: Backend::Nspawn.to_string(),
+            sudo: true,
+            ..Default::default()
+        })?;
+        let scratch = Scratch::new(&spec.quota, spec.scratch, &mut entry)?;
+        let lower_dir = tmp::TempDir::new_in(spec.scratch)?;
+        entry.add_dir(lower_dir.path())?;
+        entry.add_mount(lower_dir.path())?;
+        let (base, depth) = match spec.image {
+            RootFs::Squashfs(image_path) => {
+                log::debug!("mounting squashfs, image={}, target={}", image_path.display(), lower_dir.path().display());
+                Command::new("sudo")
+                    .arg("mount")
+                    .arg(image_path)
+                    .arg(lower_dir.path())
+                    .arg("-t")
+                    .arg("squashfs")
+                    .arg("-o")
+                    .arg("loop")
+                    .spawn()?
+                    .wait_success()?;
+                (lower_dir.path().to_path_buf(), 2)
+            }
+            RootFs::Directory(path) => (path.clone(), 1)
+        };
+        let _root_mount_pair
+            = Self::overlay(&scratch, &base, lower_dir.path())?;
+        let mut project_target = lower_dir.path().to_path_buf();
+        project_target.push("project");
+        let student_target = spec.mount_in(lower_dir.path());
+        Command::new("sudo")
+            .arg("mkdir")
+            .arg("-p")
+            .arg(project_target.as_path())
+            .spawn()?
+            .wait_success()
+            .map_err(|e| {
+                NspawnContainer::clean_up(lower_dir.path(), depth);
+                e
+            })?;
+        let _project_mount_pair = Self::overlay(&scratch, project_dir, project_target)
+            .map_err(|e| {
+                NspawnContainer::clean_up(lower_dir.path(), depth);
+                e
+            })?;
+        Command::new("sudo")
+            .arg("mkdir")
+            .arg("-p")
+            .arg(student_target.as_path())
+            .spawn()?
+            .wait_success()
+            .map_err(|e| {
+                NspawnContainer::clean_up(lower_dir.path(), depth);
+                e
+            })?;
+        let _student_mount_pair = Self::overlay(&scratch, submission_dir, student_target).map_err(|e| {
+            NspawnContainer::clean_up(lower_dir.path(), depth);
+            e
+        })?;
+        let x11 = X11::new(spec.display)
+            .and_then(|x11| entry.add_display(x11.as_ref()).map(|_| x11))
+            .map_err(|e| {
+                NspawnContainer::clean_up(lower_dir.path(), depth);
+                e
+            })?;
+        let machine = format!("helper-{}", lower_dir.path()
+            .file_name()
+            .map(|x| x.to_string_lossy().replace(|c: char| !c.is_ascii_alphanumeric(), ""))
+            .unwrap_or_default());
+        Ok(NspawnContainer {
+            lower_dir,
+            machine,
+            depth,
+            x11,
+            quota: spec.quota,
+            _root_mount_pair,
+            _project_mount_pair,
+            _student_mount_pair,
+            _entry: entry,
+            _scratch: scratch,
+        })
+    }
+    /// `depth` is the number of mounts stacked on the root: the overlay and maybe the squashfs
+    pub fn clean_up(path: &Path, depth: usize) {
+        log::info!("cleaning up container");
+        for _ in 0..depth {
+            match Command::new("sudo")
+                .arg("umount")
+                .arg("-R")
+                .arg(path)
+                .spawn()
+                .and_then_into(|mut x| x.wait_success()) {
+                Ok(()) => (),
+                Err(e) => log::error!("{}", e)
+            };
+        }
+    }
+}
+
+fn sudo_clean(dir: &Path) {
+    log::debug!("remove temporary directory {}", dir.display());
+    match dir
+        .canonicalize()
+        .and_then_into(|dir| Command::new("sudo")
+            .arg("rm")
+            .arg("-rf")
+            .arg(dir)
+            .spawn()
+            .and_then_into(|mut x| x.wait_success())) {
+        Ok(()) => (),
+        Err(e) => log::error!("failed to remove {}: {}", dir.display(), e)
+    };
+}
+
+impl Drop for NspawnContainer {
+    fn drop(&mut self) {
+        NspawnContainer::clean_up(self.lower_dir.as_ref(), self.depth);
+        sudo_clean(self._student_mount_pair.0.path());
+        sudo_clean(self._student_mount_pair.1.path());
+        sudo_clean(self._project_mount_pair.0.path());
+        sudo_clean(self._project_mount_pair.1.path());
+        sudo_clean(self._root_mount_pair.0.path());
+        sudo_clean(self._root_mount_pair.1.path());
+        sudo_clean(self.lower_dir.path());
+    }
+}
+
+impl ContainerBackend for NspawnContainer {
+    fn cmd(&self) -> Result<Command> {
+        let mut command = Command::new("sudo");
+        command
+            .arg("systemd-nspawn")
+            .arg("--quiet")
+            .arg(format!("--machine={}", self.machine))
+            .arg("-D")
+            .arg(self.lower_dir.path());
+        if let Some(x11) = &self.x11 {
+            command
+                .arg("--bind=/tmp/.X11-unix")
+                .arg("--bind")
+                .arg(x11.magic.path());
+            for (key, value) in x11.env() {
+                command.arg("-E").arg(format!("{}={}", key, value));
+            }
+        }
+        command
+            .arg("--as-pid2")
+            .arg("--chdir=/project");
+        for i in self.quota.properties() {
+            command.arg(format!("--property={}", i));
+        }
+        Ok(command)
+    }
+    fn upper_dirs(&self) -> Vec<(&'static str, std::path::PathBuf)> {
+        vec![("root", self._root_mount_pair.0.path().to_path_buf()),
+             ("project", self._project_mount_pair.0.path().to_path_buf()),
+             ("submission", self._student_mount_pair.0.path().to_path_buf())]
+    }
+    /// the upper dirs belong to root, copy with sudo and hand the result to the grader
+    fn save_upper(&self, target: &Path) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+        for (name, path) in self.upper_dirs() {
+            let mut command = Command::new("sudo");
+            command.arg("cp");
+            copy_layer(command, &path, &target.join(name))?;
+        }
+        let metadata = target.metadata()?;
+        Command::new("sudo")
+            .arg("chown")
+            .arg("-R")
+            .arg(format!("{}:{}", metadata.uid(), metadata.gid()))
+            .arg(target)
+            .spawn()?
+            .wait_success()
+    }
+    /// stay in the process group of the terminal, otherwise sudo cannot ask for a password
+    fn spawn(&self, command: &mut Command) -> Result<std::process::Child> {
+        command.spawn().map_err(Into::into)
+    }
+    /// sudo relays SIGTERM to systemd-nspawn, which then tears down the whole container
+    fn terminate(&self, child: &mut std::process::Child) -> Result<()> {
+        Command::new("kill")
+            .arg("-s")
+            .arg("TERM")
+            .arg(child.id().to_string())
+            .spawn()?
+            .wait_success()
+    }
+    /// the container did not go down, kill all of its processes through systemd
+    fn kill(&self, _child: &mut std::process::Child) -> Result<()> {
+        Command::new("sudo")
+            .arg("machinectl")
+            .arg("terminate")
+            .arg(&self.machine)
+            .spawn()?
+            .wait_success()
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::*;
+use tempfile as tmp;
+
+use crate::utils::AndThenInto;
+
+use super::{WaitSuccess, X11};
+
+/// What a container leaves behind on the host if the helper dies before dropping it
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct Record {
+    /// pid of the helper owning the container
+    pub owner: u32,
+    pub backend: String,
+    /// mounts and dirs belong to root and need `sudo`, otherwise mounts are FUSE mounts of the user
+    pub sudo: bool,
+    /// mount points, unmounted in reverse order
+    pub mounts: Vec<PathBuf>,
+    pub processes: Vec<Process>,
+    /// temporary directories removed after unmounting
+    pub dirs: Vec<PathBuf>,
+}
+
+/// A helper process that outlives its owner, e.g. Xvfb or the holder of a rootless container
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Process {
+    pub pid: u32,
+    /// the command name, checked before killing in case the pid got reused
+    pub name: String,
+    /// kill the whole process group led by `pid`
+    pub group: bool,
+}
+
+fn state_dir() -> Result<PathBuf> {
+    Ok(std::env::current_dir()?.join(".helper-state"))
+}
+
+/// Scratch space of the `n`-th judging worker, containers of different workers never share
+/// a directory
+pub fn worker_dir(n: usize) -> Result<PathBuf> {
+    let dir = std::env::current_dir()?.join(".helper-workers").join(n.to_string());
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// A record kept in `.helper-state` for as long as the container lives
+pub struct Entry {
+    path: PathBuf,
+    record: Record,
+}
+
+impl Entry {
+    /// Must be created before the first mount so that a crash at any point can be recovered
+    pub fn new(mut record: Record) -> Result<Self> {
+        let dir = state_dir()?;
+        std::fs::create_dir_all(&dir)?;
+        record.owner = std::process::id();
+        let (_, path) = tmp::Builder::new()
+            .prefix(&format!("{}-", record.owner))
+            .suffix(".json")
+            .tempfile_in(&dir)?
+            .keep()?;
+        let entry = Entry { path, record };
+        entry.save()?;
+        Ok(entry)
+    }
+    fn save(&self) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&self.record)?)
+            .map_err(Into::into)
+    }
+    pub fn add_mount(&mut self, path: &Path) -> Result<()> {
+        self.record.mounts.push(path.to_path_buf());
+        self.save()
+    }
+    pub fn add_dir(&mut self, path: &Path) -> Result<()> {
+        self.record.dirs.push(path.to_path_buf());
+        self.save()
+    }
+    pub fn add_process(&mut self, pid: u32, name: &str, group: bool) -> Result<()> {
+        self.record.processes.push(Process { pid, name: name.to_string(), group });
+        self.save()
+    }
+    pub fn add_display(&mut self, x11: Option<&X11>) -> Result<()> {
+        match x11.and_then(|x| x.server.as_ref()) {
+            Some(server) => self.add_process(server.id(), "Xvfb", false),
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::error!("failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Current mount points and file system types from `/proc/self/mountinfo`
+fn mounts() -> Result<Vec<(PathBuf, String)>> {
+    fn unescape(x: &str) -> String {
+        x.replace("\\040", " ")
+            .replace("\\011", "\t")
+            .replace("\\012", "\n")
+            .replace("\\134", "\\")
+    }
+    Ok(std::fs::read_to_string("/proc/self/mountinfo")?
+        .lines()
+        .filter_map(|line| {
+            let mut halves = line.splitn(2, " - ");
+            let target = halves.next()?.split(' ').nth(4)?;
+            let fs_type = halves.next()?.split(' ').next()?;
+            Some((PathBuf::from(unescape(target)), fs_type.to_string()))
+        })
+        .collect())
+}
+
+fn is_mounted(path: &Path) -> Result<bool> {
+    Ok(mounts()?.iter().any(|(x, _)| x == path))
+}
+
+fn alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+fn unmount(path: &Path, sudo: bool) -> Result<()> {
+    // stacked mounts (squashfs + overlay) need more than one round
+    for _ in 0..4 {
+        if !is_mounted(path)? {
+            return Ok(());
+        }
+        log::info!("unmounting {}", path.display());
+        if sudo {
+            Command::new("sudo").arg("umount").arg("-R").arg(path).spawn()?.wait_success()?;
+        } else {
+            Command::new("fusermount").arg("-u").arg(path).spawn()?.wait_success()?;
+        }
+    }
+    Err(anyhow!("{} is still mounted", path.display()))
+}
+
+fn remove(path: &Path, sudo: bool) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    log::info!("removing {}", path.display());
+    if sudo {
+        Command::new("sudo").arg("rm").arg("-rf").arg(path).spawn()?.wait_success()
+    } else {
+        // overlayfs leaves a mode 000 directory inside each work dir
+        Command::new("chmod").arg("-R").arg("u+rwX").arg(path).spawn()?.wait_success()?;
+        std::fs::remove_dir_all(path).map_err(Into::into)
+    }
+}
+
+fn release(record: &Record) -> Result<()> {
+    for i in &record.processes {
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", i.pid)).unwrap_or_default();
+        if comm.trim() == i.name {
+            log::info!("killing {} ({})", i.name, i.pid);
+            Command::new("kill")
+                .arg("-s")
+                .arg("KILL")
+                .arg("--")
+                .arg(if i.group { format!("-{}", i.pid) } else { i.pid.to_string() })
+                .spawn()?
+                .wait_success()?;
+        }
+    }
+    for i in record.mounts.iter().rev() {
+        unmount(i, record.sudo)?;
+    }
+    for i in &record.dirs {
+        remove(i, record.sudo)?;
+    }
+    Ok(())
+}
+
+/// Clean up the containers of dead helpers and report mounts left in temporary directories
+/// of the working directory or of a worker without any record. Returns the number of problems found.
+pub fn recover(dry_run: bool) -> Result<usize> {
+    let dir = state_dir()?;
+    let mut found = 0;
+    let mut claimed = Vec::new();
+    let entries = if dir.is_dir() {
+        std::fs::read_dir(&dir)?.collect::<std::io::Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+    for i in entries {
+        let record: Record = match std::fs::read(i.path())
+            .and_then_into(|x| serde_json::from_slice(&x)) {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("invalid record {}: {}", i.path().display(), e);
+                found += 1;
+                continue;
+            }
+        };
+        claimed.extend(record.mounts.iter().cloned());
+        if alive(record.owner) {
+            log::debug!("{} container of {} is still in use", record.backend, record.owner);
+            continue;
+        }
+        found += 1;
+        log::warn!("stale {} container of {}: mounts {:?}, dirs {:?}", record.backend, record.owner,
+                   record.mounts, record.dirs);
+        if !dry_run {
+            match release(&record).and_then_into(|_| std::fs::remove_file(i.path())) {
+                Ok(()) => log::info!("recovered {}", i.path().display()),
+                Err(e) => log::error!("failed to recover {}: {}", i.path().display(), e)
+            }
+        }
+    }
+    let current_dir = std::env::current_dir()?;
+    let workers = current_dir.join(".helper-workers");
+    for (target, fs_type) in mounts()? {
+        let orphan = target.parent()
+            .map(|x| x == current_dir || x.parent() == Some(workers.as_path()))
+            .unwrap_or(false)
+            && target.file_name().and_then(|x| x.to_str()).map(|x| x.starts_with(".tmp")).unwrap_or(false)
+            && !claimed.iter().any(|x| target.starts_with(x));
+        if orphan {
+            found += 1;
+            log::warn!("unrecorded {} mount at {}", fs_type, target.display());
+            if !dry_run {
+                if let Err(e) = unmount(&target, !fs_type.starts_with("fuse")) {
+                    log::error!("failed to unmount {}: {}", target.display(), e);
+                }
+            }
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::*;
+use tempfile as tmp;
+
+use crate::utils::AndThenInto;
+
+use super::*;
+use super::registry::{Entry, Record};
+
+/// The same squashfs + project + student layout as `NspawnContainer`, but all mounts live in
+/// a user/mount namespace owned by a `holder` process. Commands join the namespace with
+/// `nsenter`, so nothing needs root. Killing the holder releases every mount at once.
+pub struct RootlessContainer {
+    holder: std::process::Child,
+    root_dir: tmp::TempDir,
+    _image_dir: tmp::TempDir,
+    x11: Option<X11>,
+    /// upper and work dirs of all overlays, a size limited tmpfs inside the namespace if
+    /// there is a disk quota
+    scratch_dir: tmp::TempDir,
+    quota: Quota,
+    _entry: Entry,
+}
+
+impl RootlessContainer {
+    fn overlay<A, B>(script: &mut String, scratch: &Path, name: &str, base_path: A, target_path: B) -> Result<()>
+        where A: AsRef<Path>, B: AsRef<Path> {
+        let upper_dir = scratch.join(format!("{}.upper", name));
+        let work_dir = scratch.join(format!("{}.work", name));
+        script.push_str(&format!(
+            "mkdir -p {upper} {work} {target}\nmount -t overlay overlay -o {options} {target}\n",
+            upper = quote(&upper_dir)?,
+            work = quote(&work_dir)?,
+            target = quote(target_path.as_ref())?,
+            options = quote(format!(
+                "lowerdir={},upperdir={},workdir={},userxattr",
+                base_path.as_ref().display(),
+                upper_dir.display(),
+                work_dir.display()
+            ))?
+        ));
+        Ok(())
+    }
+    pub fn new(spec: &Spec) -> Result<Self> {
+        use std::io::BufRead;
+        let (submission_dir, project_dir) = (spec.submission, spec.project);
+        let mut entry = Entry::new(Record { backend: Backend::Rootless.to_string(), ..Default::default() })?;
+        let image_dir = tmp::TempDir::new_in(spec.scratch)?;
+        let root_dir = tmp::TempDir::new_in(spec.scratch)?;
+        let scratch_dir = tmp::TempDir::new_in(spec.scratch)?;
+        for i in &[&image_dir, &root_dir, &scratch_dir] {
+            entry.add_dir(i.path())?;
+        }
+        let x11 = X11::new(spec.display)?;
+        entry.add_display(x11.as_ref())?;
+        let root = root_dir.path().to_path_buf();
+        let scratch = scratch_dir.path();
+        let mut script = String::from("set -e\n");
+        if let Some(disk) = spec.quota.disk {
+            script.push_str(&format!("mount -t tmpfs -o size={}m tmpfs {}\n", disk, quote(scratch)?));
+        }
+        let base = match spec.image {
+            RootFs::Squashfs(image_path) => {
+                // squashfuse stays in the foreground so that it shares the process group of the holder
+                script.push_str(&format!(
+                    "squashfuse -f {image} {target} &\n\
+                     for i in $(seq 50); do mountpoint -q {target} && break; sleep 0.1; done\n\
+                     mountpoint -q {target}\n",
+                    image = quote(image_path)?,
+                    target = quote(image_dir.path())?
+                ));
+                image_dir.path().to_path_buf()
+            }
+            RootFs::Directory(path) => path.clone()
+        };
+        Self::overlay(&mut script, scratch, "root", &base, &root)?;
+        let project_target = root.join("project");
+        Self::overlay(&mut script, scratch, "project", project_dir.canonicalize()?, &project_target)?;
+        Self::overlay(&mut script, scratch, "student",
+                      submission_dir.canonicalize()?,
+                      spec.mount_in(&root))?;
+        let binds: &[&str] = if x11.is_some() {
+            &["dev", "proc", "sys", "tmp/.X11-unix"]
+        } else {
+            &["dev", "proc", "sys"]
+        };
+        for i in binds {
+            script.push_str(&format!("mkdir -p {target}\nmount --rbind {source} {target}\n",
+                                     target = quote(root.join(i))?,
+                                     source = quote(Path::new("/").join(i))?));
+        }
+        if let Some(x11) = &x11 {
+            script.push_str(&format!("touch {target}\nmount --bind {source} {target}\n",
+                                     target = quote(root.join(x11.magic.path().strip_prefix("/")?))?,
+                                     source = quote(x11.magic.path())?));
+        }
+        script.push_str("echo ready\nexec sleep infinity\n");
+        log::debug!("starting rootless holder:\n{}", script);
+        let mut holder = Command::new("setsid")
+            .arg("unshare")
+            .arg("--user")
+            .arg("--map-root-user")
+            .arg("--mount")
+            .arg("sh")
+            .arg("-c")
+            .arg(script)
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        // the holder execs into `sleep` once everything is mounted
+        entry.add_process(holder.id(), "sleep", true)?;
+        let mut line = String::new();
+        std::io::BufReader::new(holder.stdout.take().ok_or(anyhow!("holder stdout unavailable"))?)
+            .read_line(&mut line)?;
+        let container = RootlessContainer {
+            holder,
+            root_dir,
+            _image_dir: image_dir,
+            x11,
+            scratch_dir,
+            quota: spec.quota,
+            _entry: entry,
+        };
+        if line.trim() == "ready" {
+            Ok(container)
+        } else {
+            Err(anyhow!("failed to set up rootless container"))
+        }
+    }
+}
+
+impl Drop for RootlessContainer {
+    fn drop(&mut self) {
+        log::info!("cleaning up rootless container");
+        match Command::new("kill")
+            .arg("-s")
+            .arg("KILL")
+            .arg("--")
+            .arg(format!("-{}", self.holder.id()))
+            .spawn()
+            .and_then_into(|mut x| x.wait_success())
+            .and_then(|_| self.holder.wait().map_err(Into::into)) {
+            Ok(_) => (),
+            Err(e) => log::error!("failed to stop namespace holder: {}", e)
+        }
+        // overlayfs leaves a mode 000 directory inside each work dir
+        match Command::new("chmod")
+            .arg("-R")
+            .arg("u+rwX")
+            .arg(self.scratch_dir.path())
+            .spawn()
+            .and_then_into(|mut x| x.wait_success()) {
+            Ok(()) => (),
+            Err(e) => log::error!("failed to reset permissions: {}", e)
+        }
+    }
+}
+
+impl ContainerBackend for RootlessContainer {
+    fn cmd(&self) -> Result<Command> {
+        let mut command = self.quota.command("nsenter");
+        // the environment is reset inside the chroot, systemd-run still needs the one of the user
+        command
+            .arg("--target")
+            .arg(self.holder.id().to_string())
+            .arg("--user")
+            .arg("--mount")
+            .arg("chroot")
+            .arg(self.root_dir.path())
+            .arg("env")
+            .arg("-i")
+            .arg("PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
+            .arg("HOME=/root");
+        for (key, value) in self.x11.iter().flat_map(X11::env) {
+            command.arg(format!("{}={}", key, value));
+        }
+        command
+            .arg("sh")
+            .arg("-c")
+            .arg("cd /project && exec \"$@\"")
+            .arg("sh");
+        Ok(command)
+    }
+    fn upper_dirs(&self) -> Vec<(&'static str, std::path::PathBuf)> {
+        let scratch = self.scratch_dir.path();
+        vec![("root", scratch.join("root.upper")),
+             ("project", scratch.join("project.upper")),
+             ("submission", scratch.join("student.upper"))]
+    }
+    /// with a disk quota the upper dirs are on a tmpfs only visible inside the namespace
+    fn save_upper(&self, target: &Path) -> Result<()> {
+        for (name, path) in self.upper_dirs() {
+            let mut command = Command::new("nsenter");
+            command
+                .arg("--target")
+                .arg(self.holder.id().to_string())
+                .arg("--user")
+                .arg("--mount")
+                .arg("cp");
+            copy_layer(command, &path, &target.join(name))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use prettytable::*;
+
+use crate::utils::*;
+
+/// Programs the helper may shell out to and what needs them
+const PROGRAMS: &[(&str, &str)] = &[
+    ("sudo", "nspawn backend"),
+    ("systemd-nspawn", "nspawn backend"),
+    ("unshare", "rootless backend"),
+    ("nsenter", "rootless backend"),
+    ("bwrap", "bwrap backend"),
+    ("squashfuse", "squashfs images without root"),
+    ("fusermount", "squashfs images without root"),
+    ("systemd-run", "memory and process quotas"),
+    ("xauth", "X11 forwarding"),
+    ("Xvfb", "xvfb display mode"),
+    ("tar", "docker and OCI images"),
+    ("mksquashfs", "image build"),
+];
+
+/// Kernel file systems the containers are built from
+const FILESYSTEMS: &[(&str, &str)] = &[
+    ("overlay", "all container backends"),
+    ("squashfs", "squashfs images with nspawn"),
+    ("fuse", "squashfs images without root"),
+];
+
+fn which(program: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|x| x.join(program))
+        .find(|x| x.is_file())
+}
+
+fn check_sudo() -> bool {
+    Command::new("sudo")
+        .arg("-n")
+        .arg("true")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|x| x.success())
+        .unwrap_or(false)
+}
+
+/// Print the state of all prerequisites, returns the number of missing ones
+fn check_prerequisites() -> usize {
+    let mut missing = 0;
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![Cell::new("check"), Cell::new("status"), Cell::new("needed by")]));
+    for (program, usage) in PROGRAMS {
+        let status = match which(program) {
+            Some(path) => path.display().to_string(),
+            None => {
+                missing += 1;
+                String::from("missing")
+            }
+        };
+        table.add_row(Row::new(vec![Cell::new(program), Cell::new(&status), Cell::new(usage)]));
+    }
+    let filesystems = std::fs::read_to_string("/proc/filesystems").unwrap_or_default();
+    for (fs, usage) in FILESYSTEMS {
+        let status = if filesystems.lines().any(|x| x.split_whitespace().last() == Some(fs)) {
+            "available"
+        } else {
+            // may still be loaded as a module on the first mount
+            missing += 1;
+            "not loaded"
+        };
+        table.add_row(Row::new(vec![Cell::new(&format!("{} filesystem", fs)), Cell::new(status), Cell::new(usage)]));
+    }
+    let sudo = if check_sudo() {
+        "ok"
+    } else {
+        missing += 1;
+        "needs a password or is denied"
+    };
+    table.add_row(Row::new(vec![Cell::new("sudo access"), Cell::new(sudo), Cell::new("nspawn backend")]));
+    table.printstd();
+    missing
+}
+
+pub fn doctor(dry_run: bool) {
+    let missing = check_prerequisites();
+    if missing > 0 {
+        log::warn!("{} prerequisite(s) unavailable, backends depending on them will not work", missing);
+    }
+    let stale = crate::container::recover(dry_run)
+        .unwrap_with_log();
+    if stale == 0 {
+        log::info!("no stale containers found");
+    } else if dry_run {
+        log::warn!("found {} stale container(s) or mount(s), run again without --dry-run to clean up", stale);
+    } else {
+        log::info!("handled {} stale container(s) or mount(s)", stale);
+    }
+}
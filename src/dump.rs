@@ -2,7 +2,17 @@ use diesel::prelude::*;
 
 use crate::utils::*;
 
-pub fn dump(conn: &SqliteConnection, target: &str) {
+/// Copy the artifacts of a grade to `target/<project>/<student>/`
+fn extract(artifacts: &[crate::model::Artifact], target: &std::path::Path) -> anyhow::Result<()> {
+    for i in artifacts {
+        let destination = target.join(i.path.trim_start_matches('/'));
+        std::fs::create_dir_all(destination.parent().unwrap_or(target))?;
+        std::fs::copy(&i.location, &destination)?;
+    }
+    Ok(())
+}
+
+pub fn dump(conn: &SqliteConnection, target: &str, artifact_dir: Option<&std::path::Path>) {
     use crate::schema::grade::dsl as g;
     let students = crate::schema::student::table
         .load::<crate::model::Student>(conn)
@@ -25,9 +35,18 @@ pub fn dump(conn: &SqliteConnection, target: &str) {
             headers.add_cell(format!("Compile Output ({})", i.name));
             headers.add_cell(format!("Compile Stderr ({})", i.name));
             headers.add_cell(format!("Compile Return Code ({})", i.name));
+            headers.add_cell(format!("Compile Outcome ({})", i.name));
             headers.add_cell(format!("Run Output ({})", i.name));
             headers.add_cell(format!("Run Stderr ({})", i.name));
             headers.add_cell(format!("Run Return Code ({})", i.name));
+            headers.add_cell(format!("Run Outcome ({})", i.name));
+            headers.add_cell(format!("Image Hash ({})", i.name));
+            headers.add_cell(format!("Submission Hash ({})", i.name));
+            headers.add_cell(format!("Template Hash ({})", i.name));
+            headers.add_cell(format!("Artifacts ({})", i.name));
+            headers.add_cell(format!("Test Cases ({})", i.name));
+            headers.add_cell(format!("Stages ({})", i.name));
+            headers.add_cell(format!("Rubric ({})", i.name));
         }
         sw.append_row(headers).unwrap();
         for i in &students {
@@ -40,17 +59,59 @@ pub fn dump(conn: &SqliteConnection, target: &str) {
                         .and(g::project_id.eq(j.id)))
                     .first::<crate::model::Grade>(conn);
                 if let Ok(grade) = grade {
-                    row.add_cell(grade.manual_grade.to_string());
-                    row.add_cell(grade.auto_grade.to_string());
+                    row.add_cell(format_grade(grade.manual_grade, j.max_manual_grade));
+                    row.add_cell(format_grade(grade.auto_grade, j.max_auto_grade.or(grade.max_auto_grade)));
                     row.add_cell(grade.comment);
                     row.add_cell(format!("{}", grade.compile_stdout));
                     row.add_cell(format!("{}", grade.compile_stdout));
                     row.add_cell(grade.compile_return.to_string());
+                    row.add_cell(grade.compile_outcome);
                     row.add_cell(format!("{}", grade.run_stdout));
                     row.add_cell(format!("{}", grade.run_stderr));
                     row.add_cell(grade.run_return.to_string());
+                    row.add_cell(grade.run_outcome);
+                    row.add_cell(grade.image_hash.unwrap_or_else(String::new));
+                    row.add_cell(grade.submission_hash.unwrap_or_else(String::new));
+                    row.add_cell(grade.template_hash.unwrap_or_else(String::new));
+                    let artifacts = crate::schema::artifact::table
+                        .filter(crate::schema::artifact::grade_id.eq(grade.id))
+                        .load::<crate::model::Artifact>(conn)
+                        .unwrap_with_log();
+                    if let Some(dir) = artifact_dir {
+                        let student = std::path::Path::new(&i.path)
+                            .file_name()
+                            .map(|x| x.to_string_lossy().to_string())
+                            .unwrap_or_else(|| i.id.to_string());
+                        extract(&artifacts, &dir.join(&j.name).join(student))
+                            .unwrap_with_log();
+                    }
+                    row.add_cell(artifacts.iter()
+                        .map(|x| x.path.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n"));
+                    row.add_cell(crate::testcase::current(conn, Some(grade.id))
+                        .unwrap_with_log()
+                        .iter()
+                        .map(|x| match &x.message {
+                            Some(message) => format!("{}: {} ({})", x.name, x.verdict, message),
+                            None => format!("{}: {}", x.name, x.verdict),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"));
+                    row.add_cell(crate::stage::current(conn, Some(grade.id))
+                        .unwrap_with_log()
+                        .iter()
+                        .map(|x| match (x.score, x.max_score) {
+                            (Some(score), max) => format!("{}: {} ({})", x.name, x.outcome, format_grade(score, max)),
+                            (None, _) => format!("{}: {}", x.name, x.outcome),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"));
+                    row.add_cell(crate::rubric::describe(conn, grade.id)
+                        .unwrap_with_log()
+                        .join("\n"));
                 } else {
-                    row.add_empty_cells(9);
+                    row.add_empty_cells(18);
                 }
             }
             sw.append_row(row).unwrap();
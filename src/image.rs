@@ -0,0 +1,485 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::*;
+use diesel::prelude::*;
+use sha2::Digest;
+use structopt as opt;
+use tempfile as tmp;
+
+use crate::container::WaitSuccess;
+use crate::utils::*;
+
+#[derive(opt::StructOpt, Debug)]
+pub enum ImageCommand {
+    #[structopt(about = "Register an image together with its content hash")]
+    Add {
+        #[structopt(short, long, help = "Path to the image")]
+        path: PathBuf,
+    },
+    #[structopt(about = "List registered images")]
+    List,
+    #[structopt(about = "Hash the registered images again and report the ones that changed")]
+    Verify {
+        #[structopt(short, long, help = "Only verify the image with id")]
+        id: Option<i32>,
+        #[structopt(long, help = "Store the new hashes of changed images")]
+        update: bool,
+    },
+    #[structopt(about = "Build a squashfs image from a rootfs directory")]
+    Build {
+        #[structopt(short, long, help = "Path to the rootfs directory")]
+        source: PathBuf,
+        #[structopt(short, long, help = "Path to the squashfs image to create")]
+        target: PathBuf,
+        #[structopt(long, help = "Make all files owned by root inside the image")]
+        all_root: bool,
+        #[structopt(long, help = "Register the image after building")]
+        add: bool,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// a squashfs file, mounted as is
+    Squashfs,
+    /// an unpacked root filesystem
+    Directory,
+    /// a tarball created by `docker save`
+    DockerArchive,
+    /// an OCI image layout directory
+    OciLayout,
+    /// an OCI image layout packed into a tarball
+    OciArchive,
+}
+
+impl std::fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Squashfs => "squashfs",
+            ImageFormat::Directory => "directory",
+            ImageFormat::DockerArchive => "docker-archive",
+            ImageFormat::OciLayout => "oci-layout",
+            ImageFormat::OciArchive => "oci-archive",
+        })
+    }
+}
+
+/// The root filesystem a container is built upon
+#[derive(Debug, Clone)]
+pub enum RootFs {
+    Squashfs(PathBuf),
+    Directory(PathBuf),
+}
+
+fn tar_entries(path: &Path) -> Result<Vec<String>> {
+    let output = Command::new("tar")
+        .arg("-tf")
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!("failed to list {}: {}", path.display(),
+                           String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|x| x.trim_start_matches("./").to_string())
+        .collect())
+}
+
+fn untar(archive: &Path, target: &Path, extra: &[&str]) -> Result<()> {
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(target)
+        .arg("--no-same-owner")
+        .args(extra)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("failed to unpack {}", archive.display()))
+    }
+}
+
+pub fn detect(path: &Path) -> Result<ImageFormat> {
+    if path.is_dir() {
+        return Ok(if path.join("oci-layout").is_file() {
+            ImageFormat::OciLayout
+        } else {
+            ImageFormat::Directory
+        });
+    }
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)?.read_exact(&mut magic)?;
+    if &magic == b"hsqs" {
+        return Ok(ImageFormat::Squashfs);
+    }
+    let entries = tar_entries(path)
+        .map_err(|e| anyhow!("unrecognised image format of {}: {}", path.display(), e))?;
+    if entries.iter().any(|x| x == "manifest.json") {
+        Ok(ImageFormat::DockerArchive)
+    } else if entries.iter().any(|x| x == "oci-layout") {
+        Ok(ImageFormat::OciArchive)
+    } else {
+        Err(anyhow!("{} is a tarball but neither a docker archive nor an OCI layout", path.display()))
+    }
+}
+
+/// Check that `path` is a usable image and return its canonical form for the database
+pub fn locate(path: &Path) -> Result<String> {
+    let format = detect(path)?;
+    log::info!("using {} image {}", format, path.display());
+    path.canonicalize()?
+        .to_str()
+        .map(ToString::to_string)
+        .ok_or(anyhow!("invalid image path"))
+}
+
+fn read_json(path: &Path) -> Result<serde_json::Value> {
+    let file = std::fs::File::open(path)?;
+    serde_json::from_reader(file)
+        .map_err(|e| anyhow!("failed to parse {}: {}", path.display(), e))
+}
+
+fn blob(layout: &Path, digest: &str) -> Result<PathBuf> {
+    let mut split = digest.splitn(2, ':');
+    match (split.next(), split.next()) {
+        (Some(algorithm), Some(hash)) => Ok(layout.join("blobs").join(algorithm).join(hash)),
+        _ => Err(anyhow!("invalid digest: {}", digest))
+    }
+}
+
+/// Layer blobs of the first image in an OCI layout, bottom first
+fn oci_layers(layout: &Path) -> Result<Vec<PathBuf>> {
+    let mut manifest = read_json(&layout.join("index.json"))?;
+    // an index may point to further indices, follow the first entry until reaching a manifest
+    while let Some(entries) = manifest.get("manifests") {
+        let digest = entries.get(0)
+            .and_then(|x| x.get("digest"))
+            .and_then(|x| x.as_str())
+            .ok_or(anyhow!("empty image index in {}", layout.display()))?;
+        manifest = read_json(&blob(layout, digest)?)?;
+    }
+    manifest.get("layers")
+        .and_then(|x| x.as_array())
+        .ok_or(anyhow!("no layers in the manifest of {}", layout.display()))?
+        .iter()
+        .map(|x| x.get("digest")
+            .and_then(|x| x.as_str())
+            .ok_or(anyhow!("layer without digest"))
+            .and_then(|x| blob(layout, x)))
+        .collect()
+}
+
+/// Layer tarballs of the first image in an unpacked `docker save` archive, bottom first
+fn docker_layers(archive: &Path) -> Result<Vec<PathBuf>> {
+    read_json(&archive.join("manifest.json"))?
+        .get(0)
+        .and_then(|x| x.get("Layers"))
+        .and_then(|x| x.as_array())
+        .ok_or(anyhow!("no layers in manifest.json"))?
+        .iter()
+        .map(|x| x.as_str()
+            .map(|x| archive.join(x))
+            .ok_or(anyhow!("invalid layer entry")))
+        .collect()
+}
+
+/// Unpack a layer on top of `rootfs`, honouring the whiteout files of the lower layers
+fn apply_layer(layer: &Path, rootfs: &Path) -> Result<()> {
+    log::debug!("applying layer {}", layer.display());
+    for entry in tar_entries(layer)? {
+        let entry = Path::new(&entry);
+        let name = match entry.file_name().and_then(|x| x.to_str()) {
+            Some(name) if name.starts_with(".wh.") => name,
+            _ => continue
+        };
+        let parent = rootfs.join(entry.parent().unwrap_or_else(|| Path::new("")));
+        if name == ".wh..wh..opq" {
+            if parent.is_dir() {
+                for i in std::fs::read_dir(&parent)? {
+                    let path = i?.path();
+                    if path.is_dir() && !path.symlink_metadata()?.file_type().is_symlink() {
+                        std::fs::remove_dir_all(path)?;
+                    } else {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+            }
+        } else {
+            let target = parent.join(&name[4..]);
+            match target.symlink_metadata() {
+                Ok(x) if x.is_dir() => std::fs::remove_dir_all(target)?,
+                Ok(_) => std::fs::remove_file(target)?,
+                Err(_) => ()
+            }
+        }
+    }
+    untar(layer, rootfs, &["--exclude=.wh.*"])
+}
+
+fn unpack(path: &Path, format: ImageFormat, rootfs: &Path) -> Result<()> {
+    let staging = tmp::TempDir::new_in(rootfs.parent().unwrap_or_else(|| Path::new(".")))?;
+    let layers = match format {
+        ImageFormat::OciLayout => oci_layers(path)?,
+        ImageFormat::OciArchive => {
+            untar(path, staging.path(), &[])?;
+            oci_layers(staging.path())?
+        }
+        ImageFormat::DockerArchive => {
+            untar(path, staging.path(), &[])?;
+            docker_layers(staging.path())?
+        }
+        _ => return Err(anyhow!("{} images are not unpacked", format))
+    };
+    std::fs::create_dir_all(rootfs)?;
+    for i in layers {
+        apply_layer(&i, rootfs)?;
+    }
+    Ok(())
+}
+
+/// Cheap key of `path` from its location, size, modification time and inode, without reading it
+fn stat_key(path: &Path) -> Result<u64> {
+    use std::hash::{Hash, Hasher};
+    use std::os::unix::fs::MetadataExt;
+    let path = path.canonicalize()?;
+    let metadata = path.metadata()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    path.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    metadata.modified()?.hash(&mut hasher);
+    metadata.ino().hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Unpacked images are kept under `.helper-cache/images` of the working directory, keyed by
+/// `stat_key` of the image.
+fn cache_dir(path: &Path) -> Result<PathBuf> {
+    Ok(std::env::current_dir()?
+        .join(".helper-cache")
+        .join("images")
+        .join(format!("{:016x}", stat_key(path)?)))
+}
+
+/// Turn an image in any supported format into something the backends can mount
+pub fn prepare(path: &Path) -> Result<RootFs> {
+    let format = detect(path)?;
+    log::debug!("image {} has format {}", path.display(), format);
+    match format {
+        ImageFormat::Squashfs => Ok(RootFs::Squashfs(path.canonicalize()?)),
+        ImageFormat::Directory => Ok(RootFs::Directory(path.canonicalize()?)),
+        _ => {
+            let target = cache_dir(path)?;
+            if !target.is_dir() {
+                log::info!("unpacking {} image {} into {}", format, path.display(), target.display());
+                std::fs::create_dir_all(target.parent().unwrap_or_else(|| Path::new(".")))?;
+                let partial = target.with_extension("partial");
+                if partial.exists() {
+                    std::fs::remove_dir_all(&partial)?;
+                }
+                unpack(path, format, &partial)?;
+                std::fs::rename(&partial, &target)?;
+            }
+            Ok(RootFs::Directory(target))
+        }
+    }
+}
+
+/// `sha256:` digest of an image, directories are hashed over their sorted entries including
+/// paths, permissions and symlink targets
+pub fn hash(path: &Path) -> Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut hasher = sha2::Sha256::new();
+    if path.is_dir() {
+        for entry in walkdir::WalkDir::new(path).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let relative = entry.path().strip_prefix(path)?;
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(metadata.permissions().mode().to_le_bytes());
+            if entry.path_is_symlink() {
+                hasher.update(std::fs::read_link(entry.path())?.to_string_lossy().as_bytes());
+            } else if metadata.is_file() {
+                std::io::copy(&mut std::fs::File::open(entry.path())?, &mut hasher)?;
+            }
+            hasher.update([0]);
+        }
+    } else {
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Hash recorded for the image at `path`, taken from the registry when possible. Unregistered
+/// images are not read, they get a `stat:` key that changes when the image is replaced or touched.
+pub fn fingerprint(conn: &SqliteConnection, path: &Path) -> Result<String> {
+    use crate::schema::image::dsl as i;
+    let canonical = path.canonicalize()?;
+    let registered = i::image
+        .filter(i::path.eq(canonical.to_string_lossy().as_ref()))
+        .select(i::hash)
+        .first::<String>(conn)
+        .optional()?;
+    match registered {
+        Some(hash) => Ok(hash),
+        None => {
+            log::warn!("image {} is not registered, changes inside it are not detected", path.display());
+            stat_key(&canonical).map(|x| format!("stat:{:016x}", x))
+        }
+    }
+}
+
+fn register(conn: &SqliteConnection, path: &Path) -> Result<usize> {
+    let format = detect(path)?;
+    let path = locate(path)?;
+    log::info!("hashing {}", path);
+    let hash = hash(path.as_ref())?;
+    diesel::replace_into(crate::schema::image::table)
+        .values(crate::model::ChangeImage {
+            path: Some(&path),
+            format: Some(format.to_string()),
+            hash: Some(hash),
+        })
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+fn build(source: &Path, target: &Path, all_root: bool) -> Result<()> {
+    if !source.is_dir() {
+        return Err(anyhow!("{} is not a directory", source.display()));
+    }
+    let mut command = Command::new("mksquashfs");
+    command
+        .arg(source)
+        .arg(target)
+        .arg("-noappend");
+    if all_root {
+        command.arg("-all-root");
+    }
+    command.spawn()?.wait_success()
+}
+
+pub fn handle(conn: &SqliteConnection, subcommand: &ImageCommand) {
+    use crate::schema::image::dsl as i;
+    match subcommand {
+        ImageCommand::Add { path } => {
+            register(conn, path)
+                .map(|x| log::info!("updated {} item(s)", x))
+                .unwrap_with_log();
+        }
+        ImageCommand::List => {
+            let images = i::image
+                .load::<crate::model::Image>(conn)
+                .unwrap_with_log();
+            println!("{}", tablefy::into_string(&images));
+        }
+        ImageCommand::Verify { id, update } => {
+            let images = match id {
+                Some(id) => i::image.filter(i::id.eq(id)).load::<crate::model::Image>(conn),
+                None => i::image.load::<crate::model::Image>(conn),
+            }.unwrap_with_log();
+            use prettytable::*;
+            let mut table = Table::new();
+            table.add_row(Row::new(vec![Cell::new("id"), Cell::new("path"), Cell::new("status")]));
+            let mut drifted = 0;
+            for image in images {
+                let status = match hash(image.path.as_ref()) {
+                    Ok(x) if x == image.hash => String::from("ok"),
+                    Ok(x) => {
+                        drifted += 1;
+                        if *update {
+                            diesel::update(i::image.find(image.id))
+                                .set(i::hash.eq(&x))
+                                .execute(conn)
+                                .unwrap_with_log();
+                        }
+                        format!("changed ({} -> {})", image.hash, x)
+                    }
+                    Err(e) => {
+                        drifted += 1;
+                        format!("unavailable ({})", e)
+                    }
+                };
+                table.add_row(Row::new(vec![Cell::new(&image.id.to_string()),
+                                            Cell::new(&image.path),
+                                            Cell::new(&status)]));
+            }
+            table.printstd();
+            if drifted > 0 && !*update {
+                log::error!("{} image(s) differ from the registry", drifted);
+                std::process::exit(1);
+            }
+        }
+        ImageCommand::Build { source, target, all_root, add } => {
+            build(source, target, *all_root)
+                .and_then(|_| if *add { register(conn, target).map(|_| ()) } else { Ok(()) })
+                .unwrap_with_log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() -> Result<()> {
+        let dir = tmp::TempDir::new()?;
+        assert_eq!(detect(dir.path())?, ImageFormat::Directory);
+        std::fs::write(dir.path().join("oci-layout"), "{\"imageLayoutVersion\": \"1.0.0\"}")?;
+        assert_eq!(detect(dir.path())?, ImageFormat::OciLayout);
+        let squashfs = dir.path().join("image.squashfs");
+        std::fs::write(&squashfs, b"hsqs\0\0\0\0")?;
+        assert_eq!(detect(&squashfs)?, ImageFormat::Squashfs);
+        Ok(())
+    }
+
+    #[test]
+    fn test_whiteout() -> Result<()> {
+        let rootfs = tmp::TempDir::new()?;
+        std::fs::create_dir_all(rootfs.path().join("etc/opaque"))?;
+        std::fs::write(rootfs.path().join("etc/removed"), "")?;
+        std::fs::write(rootfs.path().join("etc/opaque/old"), "")?;
+        let layer = tmp::TempDir::new()?;
+        std::fs::create_dir_all(layer.path().join("etc/opaque"))?;
+        std::fs::write(layer.path().join("etc/.wh.removed"), "")?;
+        std::fs::write(layer.path().join("etc/opaque/.wh..wh..opq"), "")?;
+        std::fs::write(layer.path().join("etc/opaque/new"), "")?;
+        let archive = layer.path().join("layer.tar");
+        assert!(Command::new("tar")
+            .arg("-cf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(layer.path())
+            .arg("etc")
+            .status()?
+            .success());
+        apply_layer(&archive, rootfs.path())?;
+        assert!(!rootfs.path().join("etc/removed").exists());
+        assert!(!rootfs.path().join("etc/.wh.removed").exists());
+        assert!(!rootfs.path().join("etc/opaque/old").exists());
+        assert!(rootfs.path().join("etc/opaque/new").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash() -> Result<()> {
+        let dir = tmp::TempDir::new()?;
+        std::fs::create_dir(dir.path().join("bin"))?;
+        std::fs::write(dir.path().join("bin/sh"), "#!")?;
+        let first = hash(dir.path())?;
+        assert_eq!(first, hash(dir.path())?);
+        std::fs::write(dir.path().join("bin/sh"), "#!/")?;
+        assert_ne!(first, hash(dir.path())?);
+        let key = stat_key(&dir.path().join("bin/sh"))?;
+        assert_eq!(key, stat_key(&dir.path().join("bin/../bin/sh"))?);
+        std::fs::write(dir.path().join("bin/sh"), "#!/bin")?;
+        assert_ne!(key, stat_key(&dir.path().join("bin/sh"))?);
+        Ok(())
+    }
+}
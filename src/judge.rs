@@ -1,116 +1,1162 @@
-use std::path::PathBuf;
-use std::process::ExitStatus;
+use std::io::{Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 
 use anyhow::*;
 use diesel::{QueryDsl, SqliteConnection};
 use diesel::prelude::*;
 
+use crate::comparator::{Comparator, parse_checker_score};
 use crate::container::*;
-use crate::utils::{AndThenInto, UnwrapWithLog};
+use crate::manifest::{BUILD, Manifest, RUN};
+use crate::model::{ChangeCaseResult, ChangeStageResult};
+use crate::report::{Points, Report, ResultFormat, RESULT_FILE};
+use crate::testcase::{Case, Verdict};
+use crate::utils::{AndThenInto, check_grade, format_grade, UnwrapWithLog};
 
 /// # Project Template
 /// project-name
 /// - student/
 /// - build.sh
 /// - run.sh
+/// - tests/ (optional, see `testcase::Case`)
+/// - project.toml (optional, see `manifest::Manifest`)
+///
+/// The stages run in order in the same container: `build`, `run`, and any other stage of the
+/// manifest. A failing stage stops the pipeline unless the manifest says otherwise.
+/// Without test cases, `run.sh` reports its grade in `$HELPER_RESULT` (see `report::Report`)
+/// or as `[RESULT] x/y` on the last line of stdout, or as JUnit XML or TAP if the project says so
 /// - other-stuffs
 #[derive(structopt::StructOpt, Debug)]
 pub enum JudgeCommand {
     #[structopt(about = "Build the current project and run")]
     Go {
         #[structopt(long, short, help = "Show stdout and stderr")]
-        verbose: bool
+        verbose: bool,
+        #[structopt(long, short, help = "Container backend, overrides the project setting",
+        possible_values = BACKENDS, env = "HELPER_BACKEND")]
+        backend: Option<Backend>,
     },
-    #[structopt(about = "Edit comment")]
+    #[structopt(about = "Build, run and commit every ungraded student of a project, manual grades are left for review")]
+    Batch {
+        #[structopt(long, short, help = "The project to judge")]
+        project: i32,
+        #[structopt(long, help = "Judge all students again, keeping their manual grades and comments")]
+        all: bool,
+        #[structopt(long, conflicts_with = "all", help = "Judge again only the graded students whose \
+        submission, project template or image changed since their grade was committed")]
+        stale: bool,
+        #[structopt(long, short, help = "Container backend, overrides the project setting",
+        possible_values = BACKENDS, env = "HELPER_BACKEND")]
+        backend: Option<Backend>,
+        #[structopt(long, short, help = "Number of students judged at the same time", default_value = "1",
+        env = "HELPER_JOBS")]
+        jobs: usize,
+    },
+    #[structopt(about = "Open an interactive shell at /project inside the judging container")]
+    Shell {
+        #[structopt(long, short, help = "Container backend, overrides the project setting",
+        possible_values = BACKENDS, env = "HELPER_BACKEND")]
+        backend: Option<Backend>,
+        #[structopt(long, help = "Shell to run inside the container", default_value = "/bin/sh")]
+        shell: String,
+        #[structopt(long, help = "Run the build stage before opening the shell")]
+        build: bool,
+        #[structopt(long, help = "Copy the writable layers into this directory before tearing down")]
+        keep_upper: Option<PathBuf>,
+    },
+    #[structopt(about = "Edit comment, a line @KEY in the editor is replaced by the snippet KEY of the project")]
     Comment {
         #[structopt(long, short, help = "Set the editor to use", env = "EDITOR", default_value = "nano")]
-        editor: String
+        editor: String,
+        #[structopt(long = "snippet", short, help = "Append the snippet with this key instead of opening \
+        the editor, may be repeated")]
+        snippets: Vec<String>,
     },
     #[structopt(about = "Change manual grade")]
     ManualGrade {
-        #[structopt(long, short, help = "Target grade")]
-        grade: i32
+        #[structopt(long, short, help = "Target grade, may be fractional but not above the maximum of the project")]
+        grade: f64
     },
+    #[structopt(about = "Grade the current student criterion by criterion, the manual grade is computed \
+    from the deductions of the rubric")]
+    Rubric,
     #[structopt(about = "Change auto grade")]
     AutoGrade {
-        #[structopt(long, short, help = "Target grade")]
-        grade: i32
+        #[structopt(long, short, help = "Target grade, may be fractional but not above the maximum of the project")]
+        grade: f64
     },
 }
 
+/// How a judged script came to an end
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// the script exited by itself, successfully or not
+    Exited,
+    /// killed after running longer than the wall clock limit
+    TimeLimit,
+    /// killed by the kernel after using up the CPU time limit
+    CpuLimit,
+    /// killed by the OOM killer of the container
+    MemoryLimit,
+    /// failed to fork because of the process limit
+    PidsLimit,
+    /// ran out of space in the writable layers
+    DiskLimit,
+    /// nothing was run, the submission root could not be found
+    LayoutError,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Outcome::Exited => "exited",
+            Outcome::TimeLimit => "time-limit",
+            Outcome::CpuLimit => "cpu-limit",
+            Outcome::MemoryLimit => "memory-limit",
+            Outcome::PidsLimit => "pids-limit",
+            Outcome::DiskLimit => "disk-limit",
+            Outcome::LayoutError => "layout-error",
+        })
+    }
+}
+
+pub const DEFAULT_SUBMISSION_MOUNT: &str = "/project/src";
+/// comma separated glob patterns relative to the student directory, tried in order
+pub const DEFAULT_SUBMISSION_ROOT: &str = "src,*/src";
+/// how long a script that exceeded its time limit may take to stop before it is killed
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// The submission must go somewhere below `/project` or outside of it, but not replace it
+pub fn check_mount(mount: &str) -> Result<()> {
+    let path = Path::new(mount);
+    if !path.is_absolute() || path.components().any(|x| x == std::path::Component::ParentDir) {
+        Err(anyhow!("submission mount must be an absolute path without `..`: {}", mount))
+    } else if path == Path::new("/") || path == Path::new("/project") {
+        Err(anyhow!("submission cannot be mounted at {}", mount))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn submission_mount(project: &crate::model::Project) -> PathBuf {
+    PathBuf::from(project.submission_mount
+        .as_ref()
+        .map(AsRef::as_ref)
+        .unwrap_or(DEFAULT_SUBMISSION_MOUNT))
+}
+
+/// The first pattern matching exactly one directory gives the root of the submission
+pub fn locate_submission(student: &Path, patterns: &str) -> Result<PathBuf> {
+    let base = student.to_str()
+        .map(glob::Pattern::escape)
+        .ok_or(anyhow!("invalid student path {}", student.display()))?;
+    for pattern in patterns.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        let matches = glob::glob(&format!("{}/{}", base, pattern))?
+            .filter_map(|x| x.ok())
+            .filter(|x| x.is_dir())
+            .collect::<Vec<_>>();
+        match matches.len() {
+            0 => continue,
+            1 => {
+                log::debug!("submission root {} matched by {}", matches[0].display(), pattern);
+                return Ok(matches[0].clone());
+            }
+            _ => return Err(anyhow!("pattern {} is ambiguous in {}: {}", pattern, student.display(),
+                                    matches.iter().map(|x| x.display().to_string()).collect::<Vec<_>>().join(", ")))
+        }
+    }
+    Err(anyhow!("no directory in {} matches {}", student.display(), patterns))
+}
+
+/// Limits in seconds, `None` means unlimited
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TimeLimit {
+    pub wall: Option<u64>,
+    pub cpu: Option<u64>,
+}
+
+impl TimeLimit {
+    fn new(wall: Option<i32>, cpu: Option<i32>) -> Self {
+        TimeLimit {
+            wall: wall.map(|x| x as u64),
+            cpu: cpu.map(|x| x as u64),
+        }
+    }
+}
+
+/// Resource usage of a script and everything it waited for
+#[derive(Debug, Default, Copy, Clone)]
+struct Usage {
+    /// terminating signal, either seen directly or reported by the shell as `128 + n`
+    signal: Option<i32>,
+    core_dumped: bool,
+    wall_time: Duration,
+    cpu_time: Duration,
+    /// peak resident set size in KiB
+    peak_memory: i64,
+}
+
+impl Usage {
+    fn new(status: i32, usage: &libc::rusage, wall_time: Duration, stderr: &str) -> Self {
+        let exit_status = ExitStatus::from_raw(status);
+        let signal = exit_status.signal()
+            .or_else(|| exit_status.code().filter(|x| *x > 128 && *x <= 128 + 64).map(|x| x - 128));
+        let time = |x: libc::timeval| Duration::from_secs(x.tv_sec as u64) + Duration::from_micros(x.tv_usec as u64);
+        Usage {
+            signal,
+            // a shell only mentions the core in its error message
+            core_dumped: libc::WIFSIGNALED(status) && libc::WCOREDUMP(status) || stderr.contains("(core dumped)"),
+            wall_time,
+            cpu_time: time(usage.ru_utime) + time(usage.ru_stime),
+            peak_memory: usage.ru_maxrss as i64,
+        }
+    }
+}
+
 #[derive(Debug)]
-struct BuildResult {
+struct Execution {
     stdout: String,
     stderr: String,
     return_code: ExitStatus,
+    outcome: Outcome,
+    usage: Usage,
 }
 
-fn build(container: &Container) -> Result<BuildResult> {
-    container
-        .cmd()
-        .and_then_into(|mut x| {
-            x.arg("sh")
-                .arg("build.sh")
-                .output()
-        })
-        .and_then(|output| {
-            Ok(BuildResult {
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                return_code: output.status,
-            })
+impl Execution {
+    /// the exit code, or `128 + n` if the script was killed by signal `n`
+    fn code(&self) -> Option<i32> {
+        self.return_code.code().or_else(|| self.usage.signal.map(|x| 128 + x))
+    }
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(Err(e)) = pipe.map(|mut x| x.read_to_end(&mut buffer)) {
+            log::error!("failed to read output: {}", e);
+        }
+        String::from_utf8_lossy(&buffer).to_string()
+    })
+}
+
+/// A command run by `sh` at `/project`: a script of the template or the command of a stage
+#[derive(Debug, Clone)]
+struct Script {
+    name: String,
+    /// arguments of `sh` coming before the ones of each execution
+    argv: Vec<String>,
+    limit: TimeLimit,
+    /// exported before running, see `Manifest::env`
+    env: Vec<(String, String)>,
+    abort_on_failure: bool,
+    /// contribution to the auto grade, see `manifest::Stage::points`
+    points: Option<f64>,
+}
+
+impl Script {
+    /// `sh <path>`
+    fn file(path: &str, limit: TimeLimit, env: Vec<(String, String)>) -> Self {
+        Script {
+            name: path.to_string(),
+            argv: vec![path.to_string()],
+            limit,
+            env,
+            abort_on_failure: true,
+            points: None,
+        }
+    }
+    /// The stage `name` of the manifest, `sh <name>.sh` without one. The limits of the stage
+    /// override `limit`, the one of the project.
+    fn stage(manifest: Option<&Manifest>, name: &str, limit: TimeLimit) -> Self {
+        let env = manifest.map(Manifest::env).unwrap_or_default();
+        match manifest.and_then(|x| x.stage(name)) {
+            Some(stage) => Script {
+                name: name.to_string(),
+                // `name` becomes `$0`, so that the arguments start at `$1`
+                argv: vec![String::from("-c"), stage.command.clone(), name.to_string()],
+                limit: TimeLimit {
+                    wall: stage.time_limit.map(|x| x as u64).or(limit.wall),
+                    cpu: stage.cpu_limit.map(|x| x as u64).or(limit.cpu),
+                },
+                env,
+                abort_on_failure: stage.abort_on_failure,
+                points: stage.points,
+            },
+            None => Script {
+                name: name.to_string(),
+                ..Script::file(&format!("{}.sh", name), limit, env)
+            }
+        }
+    }
+}
+
+/// Run `script` with `args` inside the container, `input` is a host file fed to its stdin
+fn execute(container: &dyn ContainerBackend,
+           script: &Script,
+           args: &[&str],
+           input: Option<&Path>,
+           quota: Quota) -> Result<Execution> {
+    let limit = script.limit;
+    let mut prelude = format!("export HELPER_RESULT=\"$PWD/{}\" && ", RESULT_FILE);
+    for (key, value) in &script.env {
+        prelude.push_str(&format!("export {}={} && ", key, quote(value)?));
+    }
+    if let Some(cpu) = limit.cpu {
+        prelude.push_str(&format!("ulimit -t {} && ", cpu));
+    }
+    let mut command = container.cmd()?;
+    // do not exec, so that a SIGXCPU shows up as 128 + 24 in the exit code
+    command
+        .arg("sh")
+        .arg("-c")
+        .arg(format!("{}sh \"$@\"", prelude))
+        .arg("sh")
+        .args(&script.argv)
+        .args(args)
+        .stdin(match input {
+            Some(x) => Stdio::from(std::fs::File::open(x)?),
+            None => Stdio::null()
         })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let start = Instant::now();
+    let mut child = container.spawn(&mut command)?;
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let deadline = limit.wall.map(|x| start + Duration::from_secs(x));
+    // when the script was asked to stop, it is killed if it is still there after `GRACE_PERIOD`
+    let mut terminated: Option<Instant> = None;
+    let mut killed = false;
+    let pid = child.id() as libc::pid_t;
+    let (status, usage) = loop {
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        // std only reports the exit status, wait4 also returns the resource usage of the whole tree
+        match unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut usage) } {
+            x if x == pid => break (status, usage),
+            x if x < 0 => return Err(std::io::Error::last_os_error().into()),
+            _ => ()
+        }
+        match terminated {
+            None if deadline.map(|x| Instant::now() >= x).unwrap_or(false) => {
+                log::warn!("{} exceeded the time limit of {}s", script.name, limit.wall.unwrap_or(0));
+                terminated.replace(Instant::now());
+                if let Err(e) = container.terminate(&mut child) {
+                    log::error!("failed to terminate {}: {}", script.name, e);
+                }
+            }
+            Some(x) if !killed && x.elapsed() >= GRACE_PERIOD => {
+                log::warn!("{} is still running {}s after it was terminated, killing it",
+                           script.name, GRACE_PERIOD.as_secs());
+                killed = true;
+                if let Err(e) = container.kill(&mut child) {
+                    log::error!("failed to kill {}: {}", script.name, e);
+                }
+            }
+            _ => ()
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let wall_time = start.elapsed();
+    let return_code = ExitStatus::from_raw(status);
+    let stdout = stdout.join().map_err(|_| anyhow!("failed to collect stdout"))?;
+    let stderr = stderr.join().map_err(|_| anyhow!("failed to collect stderr"))?;
+    let usage = Usage::new(status, &usage, wall_time, &stderr);
+    // the quota violations are guessed from how the script died, there is no reliable way
+    // to query the cgroup through every backend
+    let outcome = if terminated.is_some() {
+        Outcome::TimeLimit
+    } else if return_code.success() {
+        Outcome::Exited
+    } else if limit.cpu.is_some() && return_code.code() == Some(128 + 24) {
+        Outcome::CpuLimit
+    } else if quota.memory.is_some() && (return_code.code() == Some(128 + 9) || return_code.signal() == Some(9)) {
+        Outcome::MemoryLimit
+    } else if quota.disk.is_some() && stderr.contains("No space left on device") {
+        Outcome::DiskLimit
+    } else if quota.pids.is_some() && (stderr.contains("Resource temporarily unavailable")
+        || stderr.contains("Cannot fork")) {
+        Outcome::PidsLimit
+    } else {
+        Outcome::Exited
+    };
+    Ok(Execution {
+        stdout,
+        stderr,
+        return_code,
+        outcome,
+        usage,
+    })
+}
+
+fn build(container: &dyn ContainerBackend, script: &Script, quota: Quota) -> Result<Execution> {
+    execute(container, script, &[], None, quota)
 }
 
 struct RunResult {
-    stdout: String,
-    stderr: String,
-    return_code: ExitStatus,
-    auto_grade: i32,
+    execution: Execution,
+    auto_grade: f64,
+    /// the denominator reported along with the grade
+    max_auto_grade: Option<f64>,
+    /// empty unless the project has test cases
+    cases: Vec<ChangeCaseResult>,
 }
 
-fn run(container: &Container) -> Result<RunResult> {
-    let output = container
-        .cmd()
-        .and_then_into(|mut x| {
-            x.arg("sh")
-                .arg("run.sh")
-                .output()
-        })?;
+/// The verdict of a case that did not exit cleanly
+fn failure(execution: &Execution) -> Option<Verdict> {
+    match execution.outcome {
+        Outcome::TimeLimit | Outcome::CpuLimit => Some(Verdict::TimeLimit),
+        Outcome::MemoryLimit => Some(Verdict::MemoryLimit),
+        Outcome::Exited if execution.return_code.success() => None,
+        _ => Some(Verdict::RuntimeError)
+    }
+}
 
-    let grade = if output.status.success() {
-        String::from_utf8_lossy(&output.stdout)
+/// Run the checker of a case with `output` on its stdin, returns the fraction of the weight
+fn check(container: &dyn ContainerBackend, checker: &Script, case: &Case, output: &str,
+         quota: Quota) -> Result<f64> {
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(output.as_bytes())?;
+    let dir = case.container_dir();
+    let x = execute(container, checker, &[&format!("{}/input", dir), &format!("{}/expected", dir)],
+                    Some(file.path()), quota)?;
+    if x.outcome != Outcome::Exited || !x.return_code.success() {
+        return Err(anyhow!("checker failed on case {} ({}, {}): {}", case.name, x.outcome, x.return_code, x.stderr));
+    }
+    parse_checker_score(&x.stdout)
+}
+
+/// Run `run.sh <case dir>` once per case, the auto grade is the weighted sum of the case scores
+fn run_cases(container: &dyn ContainerBackend, cases: &[Case], comparator: &Comparator,
+             script: &Script, quota: Quota) -> Result<RunResult> {
+    let checker = comparator.checker()
+        .map(|x| Script::file(x, script.limit, script.env.clone()));
+    let mut auto_grade = 0.0;
+    let mut results = Vec::new();
+    // the first failing case stands for the whole run in the configuration, else the last one
+    let mut representative: Option<(bool, Execution)> = None;
+    for case in cases {
+        let execution = execute(container, script, &[&case.container_dir()], case.input().as_deref(), quota)?;
+        let (verdict, fraction) = match failure(&execution) {
+            Some(x) => (x, 0.0),
+            None => {
+                let fraction = match (&checker, case.expected()?) {
+                    (Some(checker), _) => check(container, checker, case, &execution.stdout, quota)?,
+                    (None, Some(expected)) => if comparator.accepts(&execution.stdout, &expected)? { 1.0 } else { 0.0 },
+                    (None, None) => 1.0,
+                };
+                (if fraction >= 1.0 { Verdict::Accepted } else { Verdict::WrongAnswer }, fraction)
+            }
+        };
+        let score = case.weight * fraction;
+        log::info!("case {}: {} ({}/{})", case.name, verdict, score, case.weight);
+        auto_grade += score;
+        results.push(ChangeCaseResult {
+            grade_id: None,
+            name: Some(case.name.clone()),
+            verdict: Some(verdict.to_string()),
+            weight: Some(case.weight),
+            score: Some(score),
+            return_code: execution.code(),
+            wall_time: Some(execution.usage.wall_time.as_millis() as i32),
+            stdout: Some(execution.stdout.clone()),
+            stderr: Some(execution.stderr.clone()),
+            message: None,
+        });
+        let failed = verdict != Verdict::Accepted;
+        if representative.as_ref().map(|(x, _)| !*x).unwrap_or(true) {
+            representative.replace((failed, execution));
+        }
+    }
+    let (_, execution) = representative.ok_or(anyhow!("no test cases"))?;
+    Ok(RunResult {
+        execution,
+        auto_grade,
+        max_auto_grade: Some(cases.iter().map(|x| x.weight).sum()),
+        cases: results,
+    })
+}
+
+/// A file written by the last `run.sh`, `None` if there is none
+fn read_result_file(container: &dyn ContainerBackend, path: &str) -> Result<Option<String>> {
+    let output = container.cmd()?
+        .arg("sh")
+        .arg("-c")
+        .arg("if [ -f \"$1\" ]; then cat \"$1\"; else exit 3; fi")
+        .arg("sh")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).to_string())),
+        Some(3) => Ok(None),
+        _ => Err(anyhow!("reading {} failed with {}", path, output.status))
+    }
+}
+
+/// The report of the last `run.sh` in the format of the project, `None` if a JSON result is missing
+fn read_report(container: &dyn ContainerBackend, execution: &Execution,
+               format: &ResultFormat, points: &Points) -> Result<Option<Report>> {
+    match format {
+        ResultFormat::Json => read_result_file(container, RESULT_FILE)?
+            .map(|x| Report::parse_json(&x))
+            .transpose(),
+        ResultFormat::Junit(path) => read_result_file(container, path)?
+            .ok_or(anyhow!("run.sh did not write {}", path))
+            .and_then(|x| crate::report::junit::parse(&x))
+            .and_then(|x| Report::from_outcomes(x, points))
+            .map(Some),
+        ResultFormat::Tap(path) => {
+            let stream = match path {
+                Some(path) => read_result_file(container, path)?
+                    .ok_or(anyhow!("run.sh did not write {}", path))?,
+                None => execution.stdout.clone()
+            };
+            crate::report::tap::parse(&stream)
+                .and_then(|x| Report::from_outcomes(x, points))
+                .map(Some)
+        }
+    }
+}
+
+fn run(container: &dyn ContainerBackend, project: &crate::model::Project, script: &Script, quota: Quota) -> Result<RunResult> {
+    let cases = crate::testcase::load(project.path.as_ref())?;
+    if !cases.is_empty() {
+        let comparator = project.comparator
+            .as_ref()
+            .map(|x| x.parse())
+            .unwrap_or(Ok(Comparator::Exact))?;
+        return run_cases(container, &cases, &comparator, script, quota);
+    }
+    let format = project.result_format
+        .as_ref()
+        .map(|x| x.parse())
+        .unwrap_or(Ok(ResultFormat::Json))?;
+    let points: Points = project.test_points
+        .as_ref()
+        .map(|x| x.parse())
+        .unwrap_or_else(|| Ok(Points::default()))?;
+    if let Some(file) = format.file() {
+        container.cmd()?
+            .arg("rm")
+            .arg("-f")
+            .arg(file)
+            .spawn()?
+            .wait_success()?;
+    }
+    let execution = execute(container, script, &[], None, quota)?;
+    match read_report(container, &execution, &format, &points) {
+        Ok(Some(report)) => return Ok(RunResult {
+            auto_grade: report.auto_grade(),
+            max_auto_grade: report.max_score,
+            cases: report.cases(execution.code()),
+            execution,
+        }),
+        Ok(None) => (),
+        Err(e) => {
+            log::error!("failed to read the {} result: {}", format, e);
+            return Ok(RunResult { execution, auto_grade: 0.0, max_auto_grade: None, cases: Vec::new() });
+        }
+    }
+
+    // legacy protocol: `[RESULT] x/y` on the last line of stdout
+    let (grade, max_grade) = if execution.return_code.success() {
+        execution.stdout
             .lines()
             .last()
             .ok_or(anyhow::anyhow!("empty output"))
-            .and_then_into(|x|
-                x.split("/").next()
-                    .and_then(|x| x.split("[RESULT] ")
-                        .last())
-                    .ok_or(anyhow!("invalid format")))
-            .and_then_into(|x| x.parse())
+            .and_then(parse_legacy_result)
             .map_err(|x| {
                 log::error!("failed to extract auto grade: {}", x);
                 x
-            }).unwrap_or(0)
-    } else { 0 };
+            }).unwrap_or((0.0, None))
+    } else { (0.0, None) };
     Ok(RunResult {
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        return_code: output.status,
+        execution,
         auto_grade: grade,
+        max_auto_grade: max_grade,
+        cases: Vec::new(),
     })
 }
 
+/// `[RESULT] x/y`, the denominator is optional
+fn parse_legacy_result(line: &str) -> Result<(f64, Option<f64>)> {
+    let result = line.split("[RESULT] ")
+        .last()
+        .ok_or(anyhow!("invalid format"))?;
+    let mut parts = result.splitn(2, '/');
+    let grade = parts.next()
+        .unwrap_or("")
+        .trim()
+        .parse()?;
+    let max = parts.next()
+        .map(|x| x.trim().parse())
+        .transpose()?;
+    Ok((grade, max))
+}
+
+fn current_project(conn: &SqliteConnection, conf: &crate::model::Configuration) -> Result<crate::model::Project> {
+    crate::schema::project::table
+        .find(conf.current_project.ok_or(anyhow!("please set a project first"))?)
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+/// Digest of `path`, a failure only loses change detection so it is logged and not fatal
+fn try_hash(what: &str, path: &Path) -> Option<String> {
+    crate::image::hash(path)
+        .map_err(|e| log::warn!("cannot hash {} {}, it is stored without a hash: {}", what, path.display(), e))
+        .ok()
+}
+
+/// How the containers of a project are made, resolved from the database before judging
+struct Setup {
+    backend: Backend,
+    image: crate::image::RootFs,
+    image_hash: String,
+    /// only known after `hash_template`, `None` if the template could not be hashed
+    template_hash: Option<String>,
+    mount: PathBuf,
+    submission_root: String,
+    quota: Quota,
+    display: DisplayMode,
+    /// the pipeline in order, see `Manifest::pipeline`
+    stages: Vec<Script>,
+}
+
+impl Setup {
+    fn new(conn: &SqliteConnection,
+           conf: &crate::model::Configuration,
+           project: &crate::model::Project,
+           backend: Option<Backend>) -> Result<Self> {
+        let display = project.display
+            .as_ref()
+            .map(|x| x.parse())
+            .unwrap_or_else(|| Ok(DisplayMode::auto()))?;
+        let backend = backend
+            .map(Ok)
+            .or_else(|| project.backend.as_ref().map(|x| x.parse()))
+            .unwrap_or(Ok(Backend::Nspawn))?;
+        // projects without their own image share the global one
+        let image = project.image.as_ref().unwrap_or(&conf.base_image);
+        let manifest = Manifest::load(project.path.as_ref())?;
+        // stages other than `build` share the limits of `run` unless they have their own
+        let build_limit = TimeLimit::new(project.build_time_limit, project.build_cpu_limit);
+        let run_limit = TimeLimit::new(project.run_time_limit, project.run_cpu_limit);
+        let stages = manifest.as_ref()
+            .map(Manifest::pipeline)
+            .unwrap_or_else(|| vec![BUILD, RUN])
+            .into_iter()
+            .map(|x| Script::stage(manifest.as_ref(), x, if x == BUILD { build_limit } else { run_limit }))
+            .collect();
+        Ok(Setup {
+            stages,
+            backend,
+            image_hash: crate::image::fingerprint(conn, image.as_ref())?,
+            template_hash: None,
+            image: crate::image::prepare(image.as_ref())?,
+            mount: submission_mount(project),
+            submission_root: project.submission_root
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SUBMISSION_ROOT.to_string()),
+            quota: Quota::new(project.memory_limit, project.pids_limit, project.disk_limit),
+            display,
+        })
+    }
+    fn stage(&self, name: &str) -> Option<&Script> {
+        self.stages.iter().find(|x| x.name == name)
+    }
+    /// Points of all graded stages other than `run`
+    fn stage_points(&self) -> f64 {
+        self.stages.iter().filter_map(|x| x.points).sum()
+    }
+    /// Hash the template once for all students judged with this setup
+    fn hash_template(&mut self, project: &crate::model::Project) {
+        self.template_hash = try_hash("template", project.path.as_ref());
+    }
+    /// Digest of the submission of `student`, or of the whole student directory if the
+    /// layout is not recognised, so that fixing the layout counts as a change
+    fn submission_hash(&self, student: &Path) -> Option<String> {
+        match locate_submission(student, &self.submission_root) {
+            Ok(submission) => try_hash("submission", &submission),
+            Err(_) => try_hash("submission", student),
+        }
+    }
+    /// Why the inputs of `grade` differ from the current ones, empty if it is up to date. A
+    /// submission that cannot be hashed counts as changed, a template that cannot is ignored.
+    fn changes(&self, grade: &crate::model::Grade, student: &Path) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        let submission = self.submission_hash(student);
+        if submission.is_none() || grade.submission_hash != submission {
+            changes.push("submission");
+        }
+        if self.template_hash.is_some() && grade.template_hash != self.template_hash {
+            changes.push("template");
+        }
+        if grade.image_hash.as_ref() != Some(&self.image_hash) {
+            changes.push("image");
+        }
+        changes
+    }
+    /// a container with `submission` mounted, its temporary directories are created in `scratch`
+    fn create(&self, project: &crate::model::Project, submission: &Path, scratch: &Path)
+              -> Result<Box<dyn ContainerBackend>> {
+        self.backend.create(&Spec {
+            image: &self.image,
+            submission,
+            mount: self.mount.as_path(),
+            project: project.path.as_ref(),
+            quota: self.quota,
+            display: self.display,
+            scratch,
+        })
+    }
+}
+
+fn store_layout_error(conf: &mut crate::model::Configuration, error: &str) {
+    conf.compile_stdout.replace(String::new());
+    conf.compile_stderr.replace(error.to_string());
+    conf.compile_return = None;
+    conf.compile_outcome.replace(Outcome::LayoutError.to_string());
+}
+
+fn store_compile(conf: &mut crate::model::Configuration, x: Execution) {
+    conf.compile_return = x.code();
+    conf.compile_signal = x.usage.signal;
+    conf.compile_core_dumped.replace(x.usage.core_dumped);
+    conf.compile_wall_time.replace(x.usage.wall_time.as_millis() as i32);
+    conf.compile_cpu_time.replace(x.usage.cpu_time.as_millis() as i32);
+    conf.compile_peak_memory.replace(x.usage.peak_memory as i32);
+    conf.compile_stdout.replace(x.stdout);
+    conf.compile_stderr.replace(x.stderr);
+    conf.compile_outcome.replace(x.outcome.to_string());
+}
+
+/// The case results are not part of the configuration, they are recorded separately
+fn store_run(conf: &mut crate::model::Configuration, x: Execution) {
+    conf.run_return = x.code();
+    conf.run_signal = x.usage.signal;
+    conf.run_core_dumped.replace(x.usage.core_dumped);
+    conf.run_wall_time.replace(x.usage.wall_time.as_millis() as i32);
+    conf.run_cpu_time.replace(x.usage.cpu_time.as_millis() as i32);
+    conf.run_peak_memory.replace(x.usage.peak_memory as i32);
+    conf.run_stdout.replace(x.stdout);
+    conf.run_stderr.replace(x.stderr);
+    conf.run_outcome.replace(x.outcome.to_string());
+}
+
+/// The auto grade of the whole pipeline, capped at the maximum of the project
+fn store_grade(conf: &mut crate::model::Configuration, project: &crate::model::Project,
+               auto_grade: f64, max_auto_grade: Option<f64>) {
+    conf.auto_grade.replace(match project.max_auto_grade {
+        Some(max) if auto_grade > max => {
+            log::warn!("auto grade {} exceeds the maximum {} of the project", auto_grade, max);
+            max
+        }
+        _ => auto_grade
+    });
+    conf.max_auto_grade = max_auto_grade;
+}
+
+/// Whether a script ran to a clean exit, `stage` is `compile`, `runtime` or the name of another
+/// stage. The failure of a
+/// script that does not abort on failure is only logged.
+fn check_exit(stage: &str, script: &Script, x: &Execution) -> Result<()> {
+    if !script.abort_on_failure && (x.outcome != Outcome::Exited || !x.return_code.success()) {
+        log::warn!("{} ({}) did not succeed, judging goes on: {}, {}", stage, script.name, x.outcome, x.return_code);
+        Ok(())
+    } else if x.outcome != Outcome::Exited {
+        Err(anyhow!("{} stopped: {}", stage, x.outcome))
+    } else if x.return_code.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} failed", stage))
+    }
+}
+
+/// Build the container of the current student and project, exactly as `judge go` sees it
+fn container(conn: &SqliteConnection,
+             conf: &mut crate::model::Configuration,
+             project: &crate::model::Project,
+             backend: Option<Backend>) -> Result<(Setup, Box<dyn ContainerBackend>)> {
+    let student: crate::model::Student = crate::schema::student::table
+        .find(conf.current_student.ok_or(anyhow!("please set a student first"))?)
+        .get_result(conn)?;
+    let setup = Setup::new(conn, conf, project, backend)?;
+    let submission = locate_submission(Path::new(&student.path), &setup.submission_root)
+        .or_else(|e| {
+            store_layout_error(conf, &e.to_string());
+            conf.store(conn)
+                .and(Err(anyhow!("submission layout not recognised: {}", e)))
+        })?;
+    setup.create(project, &submission, &std::env::current_dir()?)
+        .map(|x| (setup, x))
+}
+
+/// Points earned by a graded stage: all of them for a clean exit, scaled by `[RESULT] x/y` on
+/// the last line of stdout if the stage reports one
+fn stage_score(x: &Execution, points: f64) -> f64 {
+    if x.outcome != Outcome::Exited || !x.return_code.success() {
+        return 0.0;
+    }
+    match x.stdout.lines().last().filter(|x| x.contains("[RESULT]")).map(parse_legacy_result) {
+        Some(Ok((grade, Some(max)))) if max > 0.0 => points * (grade / max).clamp(0.0, 1.0),
+        _ => points
+    }
+}
+
+fn stage_result(position: usize, script: &Script, x: &Execution,
+                score: Option<f64>, max_score: Option<f64>) -> ChangeStageResult {
+    ChangeStageResult {
+        grade_id: None,
+        position: Some(position as i32),
+        name: Some(script.name.clone()),
+        outcome: Some(x.outcome.to_string()),
+        return_code: x.code(),
+        wall_time: Some(x.usage.wall_time.as_millis() as i32),
+        stdout: Some(x.stdout.clone()),
+        stderr: Some(x.stderr.clone()),
+        score,
+        max_score,
+    }
+}
+
+/// What a worker found out about one student, written to the database by the batch alone
+struct Judgement {
+    student: crate::model::Student,
+    submission_hash: Option<String>,
+    /// the submission could not be found
+    layout_error: Option<String>,
+    compile: Option<Execution>,
+    run: Option<RunResult>,
+    /// every stage that ran, in order
+    stages: Vec<ChangeStageResult>,
+    /// points earned by the graded stages other than `run`
+    stage_grade: f64,
+    /// container paths and stored locations, see `artifact::collect`
+    artifacts: Vec<(PathBuf, PathBuf)>,
+    /// why judging stopped early
+    error: Option<Error>,
+}
+
+/// Run the pipeline on `student` without touching the database, containers are created in `scratch`
+fn judge(setup: &Setup, project: &crate::model::Project, student: crate::model::Student, scratch: &Path) -> Judgement {
+    let mut judgement = Judgement {
+        student,
+        submission_hash: None,
+        layout_error: None,
+        compile: None,
+        run: None,
+        stages: Vec::new(),
+        stage_grade: 0.0,
+        artifacts: Vec::new(),
+        error: None,
+    };
+    let student = PathBuf::from(&judgement.student.path);
+    judgement.submission_hash = setup.submission_hash(&student);
+    let result = locate_submission(&student, &setup.submission_root)
+        .map_err(|e| {
+            judgement.layout_error.replace(e.to_string());
+            anyhow!("submission layout not recognised: {}", e)
+        })
+        .and_then(|submission| setup.create(project, &submission, scratch))
+        .and_then(|container| {
+            for (position, script) in setup.stages.iter().enumerate() {
+                let status = if script.name == RUN {
+                    let result = run(container.as_ref(), project, script, setup.quota)?;
+                    match crate::artifact::collect(container.as_ref(), project, &setup.mount,
+                                                   judgement.student.id, scratch) {
+                        Ok(x) => judgement.artifacts = x,
+                        Err(e) => log::error!("failed to capture artifacts of {}: {}", judgement.student.path, e)
+                    }
+                    judgement.stages.push(stage_result(position, script, &result.execution,
+                                                       Some(result.auto_grade), result.max_auto_grade));
+                    let status = check_exit("runtime", script, &result.execution);
+                    judgement.run.replace(result);
+                    status
+                } else {
+                    let x = execute(container.as_ref(), script, &[], None, setup.quota)?;
+                    let score = script.points.map(|points| stage_score(&x, points));
+                    judgement.stage_grade += score.unwrap_or(0.0);
+                    judgement.stages.push(stage_result(position, script, &x, score, script.points));
+                    if script.name == BUILD {
+                        let status = check_exit("compile", script, &x);
+                        judgement.compile.replace(x);
+                        status
+                    } else {
+                        check_exit(&format!("stage {}", script.name), script, &x)
+                    }
+                };
+                status?;
+            }
+            Ok(())
+        });
+    judgement.error = result.err();
+    judgement
+}
+
+/// Put a judgement into the configuration and the tables of the current result, the
+/// configuration is not saved. Returns why judging stopped early, if it did.
+fn record(conn: &SqliteConnection,
+          conf: &mut crate::model::Configuration,
+          setup: &Setup,
+          project: &crate::model::Project,
+          judgement: &mut Judgement) -> Result<Option<Error>> {
+    conf.image_hash.replace(setup.image_hash.clone());
+    conf.template_hash = setup.template_hash.clone();
+    conf.submission_hash = judgement.submission_hash.take();
+    if let Some(e) = &judgement.layout_error {
+        store_layout_error(conf, e);
+    }
+    if let Some(x) = judgement.compile.take() {
+        store_compile(conf, x);
+    }
+    crate::testcase::clean(conn)?;
+    if let Some(result) = judgement.run.take() {
+        crate::testcase::record(conn, &result.cases)?;
+        store_grade(conf, project, judgement.stage_grade + result.auto_grade,
+                    result.max_auto_grade.map(|x| x + setup.stage_points()));
+        store_run(conf, result.execution);
+    } else if judgement.stages.iter().any(|x| x.score.is_some()) {
+        store_grade(conf, project, judgement.stage_grade, None);
+    }
+    crate::stage::record(conn, &judgement.stages)?;
+    crate::artifact::record(conn, &judgement.artifacts)?;
+    Ok(judgement.error.take())
+}
+
+/// Run the pipeline on the current student and store the results in the configuration
+fn go(conn: &SqliteConnection,
+      conf: &mut crate::model::Configuration,
+      project: &crate::model::Project,
+      backend: Option<Backend>,
+      verbose: bool) -> Result<()> {
+    let student: crate::model::Student = crate::schema::student::table
+        .find(conf.current_student.ok_or(anyhow!("please set a student first"))?)
+        .get_result(conn)?;
+    let mut setup = Setup::new(conn, conf, project, backend)?;
+    setup.hash_template(project);
+    let mut judgement = judge(&setup, project, student, &std::env::current_dir()?);
+    if verbose {
+        for x in &judgement.stages {
+            let name = x.name.as_deref().unwrap_or_default();
+            log::info!("Stage {}: {}, return code {}", name, x.outcome.as_deref().unwrap_or_default(),
+                       x.return_code.map(|x| x.to_string()).unwrap_or_else(|| String::from("unknown")));
+            log::info!("{} Stdout: \n{}", name, x.stdout.as_deref().unwrap_or_default());
+            log::info!("{} Stderr: \n{}", name, x.stderr.as_deref().unwrap_or_default());
+        }
+    }
+    conf.clean_result();
+    conf.auto_grade.take();
+    conf.max_auto_grade.take();
+    let error = record(conn, conf, &setup, project, &mut judgement)?;
+    conf.store(conn)?;
+    match error {
+        Some(e) => Err(e),
+        None => Ok(())
+    }
+}
+
+/// Which students of a project `judge batch` picks up
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Selection {
+    /// the ones without a grade
+    Ungraded,
+    All,
+    /// the graded ones whose submission, template or image changed since the commit
+    Stale,
+}
+
+/// Judge the students of a project on `jobs` workers at the same time and commit them one by
+/// one as they finish. Workers never touch the database, this thread is its only writer. The
+/// configuration is the scratch space of every student, so it must not hold an uncommitted one.
+fn batch(conn: &SqliteConnection,
+         mut conf: crate::model::Configuration,
+         project_id: i32,
+         selection: Selection,
+         backend: Option<Backend>,
+         jobs: usize) -> Result<()> {
+    use std::sync::{Arc, mpsc, Mutex};
+    use crate::schema::grade::dsl as g;
+    use crate::schema::student::dsl as s;
+    if conf.current_student.is_some() {
+        return Err(anyhow!("Please commit current student first"));
+    }
+    if jobs == 0 {
+        return Err(anyhow!("at least one job is needed"));
+    }
+    let previous_project = conf.current_project;
+    let project: crate::model::Project = crate::schema::project::table
+        .find(project_id)
+        .get_result(conn)
+        .map_err(|e| anyhow!("no such project: {}", e))?;
+    let mut setup = Setup::new(conn, &conf, &project, backend)?;
+    setup.hash_template(&project);
+    let students: Vec<crate::model::Student> = match selection {
+        Selection::All => s::student.order(s::id).load(conn)?,
+        Selection::Ungraded => s::student.filter(diesel::dsl::not(
+            diesel::dsl::exists(
+                g::grade.filter(g::project_id
+                    .eq(project_id)
+                    .and(g::student_id.eq(s::id))))))
+            .order(s::id)
+            .load(conn)?,
+        Selection::Stale => {
+            let mut stale = Vec::new();
+            let graded: Vec<(crate::model::Grade, crate::model::Student)> = g::grade
+                .inner_join(s::student)
+                .filter(g::project_id.eq(project_id))
+                .order(s::id)
+                .load(conn)?;
+            for (grade, student) in graded {
+                let changes = setup.changes(&grade, Path::new(&student.path));
+                if changes.is_empty() {
+                    log::debug!("{} is up to date", student.path);
+                } else {
+                    log::info!("{} changed: {}", student.path, changes.join(", "));
+                    stale.push(student);
+                }
+            }
+            stale
+        }
+    };
+    let total = students.len();
+    let setup = Arc::new(setup);
+    let project = Arc::new(project);
+    let (job_sender, job_receiver) = mpsc::channel();
+    for i in students {
+        job_sender.send(i)?;
+    }
+    drop(job_sender);
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+    let (sender, receiver) = mpsc::channel();
+    let mut workers = Vec::new();
+    for n in 0..jobs.min(total) {
+        let scratch = crate::container::worker_dir(n)?;
+        let (setup, project, jobs, sender) = (setup.clone(), project.clone(), job_receiver.clone(), sender.clone());
+        workers.push(std::thread::spawn(move || loop {
+            let student = match jobs.lock().map(|x| x.recv()) {
+                Ok(Ok(x)) => x,
+                _ => break
+            };
+            log::info!("worker {}: judging {}", n, student.path);
+            if sender.send(judge(&setup, &project, student, &scratch)).is_err() {
+                break;
+            }
+        }));
+    }
+    drop(sender);
+    let mut summary = prettytable::Table::new();
+    summary.add_row(prettytable::Row::new(["id", "student", "result", "auto grade"].iter()
+        .map(|x| prettytable::Cell::new(x))
+        .collect()));
+    let (mut passed, mut failed, mut errors) = (0, 0, 0);
+    for (n, mut judgement) in receiver.iter().enumerate() {
+        conf.clean_result();
+        conf.auto_grade.take();
+        conf.max_auto_grade.take();
+        conf.manual_grade.take();
+        conf.comment.take();
+        // judging again must not lose the review
+        if let Ok(grade) = g::grade
+            .filter(g::student_id.eq(judgement.student.id).and(g::project_id.eq(project_id)))
+            .first::<crate::model::Grade>(conn) {
+            conf.manual_grade.replace(grade.manual_grade);
+            conf.comment.replace(grade.comment);
+        }
+        conf.current_project.replace(project_id);
+        conf.current_student.replace(judgement.student.id);
+        let error = record(conn, &mut conf, &setup, &project, &mut judgement)?;
+        let student = &judgement.student;
+        let auto_grade = conf.auto_grade
+            .map(|x| format_grade(x, project.max_auto_grade.or(conf.max_auto_grade)))
+            .unwrap_or_default();
+        let status = match error {
+            None => {
+                passed += 1;
+                String::from("ok")
+            }
+            // nothing was recorded, e.g. the container could not be created: try again next time
+            Some(e) if conf.compile_outcome.is_none() && judgement.stages.is_empty() => {
+                log::error!("[{}/{}] failed to judge {}: {}", n + 1, total, student.path, e);
+                errors += 1;
+                conf.current_student.take();
+                conf.clean_result();
+                crate::artifact::clean(conn)?;
+                crate::testcase::clean(conn)?;
+                crate::stage::clean(conn)?;
+                conf.store(conn)?;
+                summary.add_row(prettytable::Row::new(vec![
+                    prettytable::Cell::new(&student.id.to_string()),
+                    prettytable::Cell::new(&student.path),
+                    prettytable::Cell::new(&format!("not committed: {}", e)),
+                    prettytable::Cell::new(""),
+                ]));
+                continue;
+            }
+            Some(e) => {
+                failed += 1;
+                e.to_string()
+            }
+        };
+        log::info!("[{}/{}] {}: {}", n + 1, total, student.path, status);
+        conf.commit(conn)?;
+        summary.add_row(prettytable::Row::new(vec![
+            prettytable::Cell::new(&student.id.to_string()),
+            prettytable::Cell::new(&student.path),
+            prettytable::Cell::new(&status),
+            prettytable::Cell::new(&auto_grade),
+        ]));
+    }
+    for i in workers {
+        i.join().map_err(|_| anyhow!("a judging worker panicked"))?;
+    }
+    conf.current_project = previous_project;
+    conf.store(conn)?;
+    summary.printstd();
+    log::info!("{} student(s) judged: {} passed, {} failed, {} not committed", total, passed, failed, errors);
+    Ok(())
+}
+
 pub fn handle(conn: &SqliteConnection, subcommand: &JudgeCommand) {
     let mut conf = crate::model::Configuration::get_global(conn)
         .unwrap_with_log();
-    if conf.current_student.is_none() {
+    if conf.current_student.is_none() && !matches!(subcommand, JudgeCommand::Batch { .. }) {
         log::error!("please set a student first");
         std::process::exit(1);
     }
     match subcommand {
-        JudgeCommand::Comment { editor } => {
+        JudgeCommand::Comment { snippets, .. } if !snippets.is_empty() => {
+            current_project(conn, &conf)
+                .and_then(|project| crate::snippet::Bank::new(conn, &conf, &project))
+                .and_then(|bank| snippets.iter()
+                    .map(|x| bank.take(x)?.ok_or(anyhow!("no snippet {}, expected one of {}", x,
+                                                         bank.keys().join(", "))))
+                    .collect::<Result<Vec<_>>>())
+                .and_then(|texts| {
+                    let mut comment = conf.comment.take().unwrap_or_default();
+                    for i in texts {
+                        if !comment.is_empty() && !comment.ends_with('\n') {
+                            comment.push('\n');
+                        }
+                        comment.push_str(&i);
+                    }
+                    conf.comment.replace(comment);
+                    conf.store(conn)
+                })
+                .unwrap_with_log();
+        }
+        JudgeCommand::Comment { editor, .. } => {
+            let bank = current_project(conn, &conf)
+                .and_then(|project| crate::snippet::Bank::new(conn, &conf, &project))
+                .unwrap_with_log();
+            if !bank.keys().is_empty() {
+                log::info!("snippets: {}", bank.keys().join(", "));
+            }
             dialoguer::Editor::new()
                 .executable(editor)
                 .edit(conf.comment.as_ref().map(AsRef::as_ref).unwrap_or(""))
@@ -118,7 +1164,7 @@ pub fn handle(conn: &SqliteConnection, subcommand: &JudgeCommand) {
                     match new_comment {
                         None => Ok(()),
                         Some(comment) => {
-                            conf.comment.replace(comment);
+                            conf.comment.replace(bank.expand(&comment));
                             conf.store(conn)
                                 .and(Ok(()))
                         }
@@ -127,68 +1173,162 @@ pub fn handle(conn: &SqliteConnection, subcommand: &JudgeCommand) {
                 .unwrap_with_log()
         }
         JudgeCommand::ManualGrade { grade } => {
-            conf.manual_grade.replace(*grade);
-            conf.store(conn)
+            current_project(conn, &conf)
+                .and_then(|project| check_grade(*grade, project.max_manual_grade))
+                .and_then(|_| {
+                    conf.manual_grade.replace(*grade);
+                    conf.store(conn)
+                })
                 .unwrap_with_log();
         }
-        JudgeCommand::AutoGrade { grade } => {
-            conf.auto_grade.replace(*grade);
-            conf.store(conn)
+        JudgeCommand::Rubric => {
+            current_project(conn, &conf)
+                .and_then(|project| crate::rubric::grade(conn, &mut conf, &project)
+                    .map(|x| log::info!("manual grade: {}", format_grade(x, project.max_manual_grade))))
                 .unwrap_with_log();
         }
-        JudgeCommand::Go { verbose } => {
-            let project: crate::model::Project = crate::schema::project::table
-                .find(conf.current_project.clone()
-                    .unwrap())
-                .get_result(conn)
+        JudgeCommand::AutoGrade { grade } => {
+            current_project(conn, &conf)
+                .and_then(|project| check_grade(*grade, project.max_auto_grade.or(conf.max_auto_grade)))
+                .and_then(|_| {
+                    conf.auto_grade.replace(*grade);
+                    conf.store(conn)
+                })
                 .unwrap_with_log();
-            let student: crate::model::Student = crate::schema::student::table
-                .find(conf.current_student.clone()
-                    .unwrap())
-                .get_result(conn)
+        }
+        JudgeCommand::Shell { backend, shell, build: prebuild, keep_upper } => {
+            let project = current_project(conn, &conf)
                 .unwrap_with_log();
-            let student_path = PathBuf::from(student.path);
-            Container::new(
-                conf.base_image.as_ref(),
-                student_path.as_path(),
-                project.path.as_ref(),
-            ).and_then(|x| {
-                build(&x)
-                    .map(|y| (y, x))
-            }).and_then(|(x, container)| {
-                if *verbose {
-                    log::info!("Return Code: {}", x.return_code.code()
-                        .map(|x| x.to_string())
-                        .unwrap_or_else(|| String::from("unknown")));
-                    log::info!("Compile Stdout: \n{}", x.stdout);
-                    log::info!("Compile Stderr: \n{}", x.stderr);
+            container(conn, &mut conf, &project, *backend).and_then(|(setup, container)| {
+                if *prebuild {
+                    let script = setup.stage(BUILD).ok_or(anyhow!("the pipeline has no {} stage", BUILD))?;
+                    let x = build(container.as_ref(), script, setup.quota)?;
+                    log::info!("{} finished with {} ({})", script.name, x.return_code, x.outcome);
                 }
-                conf.compile_stdout.replace(x.stdout);
-                conf.compile_stderr.replace(x.stderr);
-                conf.compile_return = x.return_code.code();
-                conf.store(conn)
-                    .and(if x.return_code.success() {Ok(container)} else {
-                        Err(anyhow!("compile failed"))
-                    })
-            }).and_then(|container| {
-                run(&container)
-            }).and_then(|x| {
-                if *verbose {
-                    log::info!("Return Code: {}", x.return_code.code()
-                        .map(|x| x.to_string())
-                        .unwrap_or_else(|| String::from("unknown")));
-                    log::info!("Run Stdout: \n{}", x.stdout);
-                    log::info!("Run Stderr: \n{}", x.stderr);
+                // the shell needs the terminal, so it is not moved into a process group of its own
+                let status = container.cmd()?
+                    .arg(shell)
+                    .status()?;
+                log::info!("shell exited with {}", status);
+                match keep_upper {
+                    Some(target) => {
+                        log::info!("saving writable layers to {}", target.display());
+                        container.save_upper(target)
+                    }
+                    None => Ok(())
                 }
-                conf.run_stdout.replace(x.stdout);
-                conf.run_stderr.replace(x.stderr);
-                conf.run_return = x.return_code.code();
-                conf.auto_grade.replace(x.auto_grade);
-                conf.store(conn)
-                    .and(if x.return_code.success() {Ok(())} else {
-                        Err(anyhow!("runtime failed"))
-                    })
             }).unwrap_with_log();
         }
+        JudgeCommand::Go { verbose, backend } => {
+            let project = current_project(conn, &conf)
+                .unwrap_with_log();
+            go(conn, &mut conf, &project, *backend, *verbose)
+                .unwrap_with_log();
+        }
+        JudgeCommand::Batch { project, all, stale, backend, jobs } => {
+            let selection = if *all {
+                Selection::All
+            } else if *stale {
+                Selection::Stale
+            } else {
+                Selection::Ungraded
+            };
+            batch(conn, conf, *project, selection, *backend, *jobs)
+                .unwrap_with_log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_locate_submission() -> Result<()> {
+        let student = tempfile::TempDir::new()?;
+        std::fs::create_dir_all(student.path().join("hw1/src"))?;
+        assert_eq!(locate_submission(student.path(), DEFAULT_SUBMISSION_ROOT)?, student.path().join("hw1/src"));
+        std::fs::create_dir_all(student.path().join("src"))?;
+        assert_eq!(locate_submission(student.path(), DEFAULT_SUBMISSION_ROOT)?, student.path().join("src"));
+        std::fs::create_dir_all(student.path().join("hw2/src"))?;
+        assert!(locate_submission(student.path(), "*/src").is_err());
+        assert!(locate_submission(student.path(), "code").is_err());
+        assert_eq!(locate_submission(student.path(), ".")?, student.path().join("."));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_legacy_result() -> Result<()> {
+        assert_eq!(parse_legacy_result("[RESULT] 7/10")?, (7.0, Some(10.0)));
+        assert_eq!(parse_legacy_result("done [RESULT] 7.5 / 10")?, (7.5, Some(10.0)));
+        assert_eq!(parse_legacy_result("[RESULT] 3")?, (3.0, None));
+        assert!(parse_legacy_result("[RESULT] seven/10").is_err());
+        assert!(parse_legacy_result("[RESULT] 7/ten").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_mount() {
+        assert!(check_mount("/project/src").is_ok());
+        assert!(check_mount("/opt/submission").is_ok());
+        assert!(check_mount("/project").is_err());
+        assert!(check_mount("project/src").is_err());
+        assert!(check_mount("/project/../etc").is_err());
+    }
+
+    /// Runs commands in a directory with SIGTERM ignored, and only signals the leader on
+    /// `terminate` like the nspawn backend
+    struct Stubborn(tempfile::TempDir);
+
+    impl ContainerBackend for Stubborn {
+        fn cmd(&self) -> Result<std::process::Command> {
+            let mut command = std::process::Command::new("sh");
+            command
+                .current_dir(self.0.path())
+                .arg("-c")
+                .arg("trap '' TERM && exec \"$@\"")
+                .arg("sh");
+            Ok(command)
+        }
+        fn upper_dirs(&self) -> Vec<(&'static str, PathBuf)> {
+            Vec::new()
+        }
+        fn terminate(&self, child: &mut std::process::Child) -> Result<()> {
+            std::process::Command::new("kill")
+                .arg("-s")
+                .arg("TERM")
+                .arg(child.id().to_string())
+                .spawn()?
+                .wait_success()
+        }
+    }
+
+    #[test]
+    fn test_execute_timeout() -> Result<()> {
+        let container = Stubborn(tempfile::TempDir::new()?);
+        std::fs::write(container.0.path().join("run.sh"), "trap '' TERM; sleep 30")?;
+        let script = Script::file("run.sh", TimeLimit { wall: Some(1), cpu: None }, Vec::new());
+        let start = Instant::now();
+        let execution = execute(&container, &script, &[], None, Quota::new(None, None, None))?;
+        assert_eq!(execution.outcome, Outcome::TimeLimit);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_try_hash() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let template = tempfile::TempDir::new()?;
+        std::fs::write(template.path().join("run.sh"), "echo hi")?;
+        std::os::unix::fs::symlink("missing", template.path().join("dangling"))?;
+        assert!(try_hash("template", template.path()).is_some());
+        let secret = template.path().join("secret");
+        std::fs::write(&secret, "answer")?;
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o000))?;
+        // root reads the file anyway
+        let root = unsafe { libc::geteuid() } == 0;
+        assert_eq!(try_hash("template", template.path()).is_some(), root);
+        assert!(try_hash("template", &template.path().join("nothing")).is_none());
+        Ok(())
+    }
+}
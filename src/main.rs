@@ -4,7 +4,7 @@ extern crate simple_excel_writer as excel;
 
 use std::path::PathBuf;
 
-use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
 use structopt as opt;
 use structopt::StructOpt;
 
@@ -14,6 +14,8 @@ use crate::judge::JudgeCommand;
 use crate::model::ChangeStudent;
 
 
+mod artifact;
+mod comparator;
 mod container;
 mod schema;
 mod model;
@@ -21,6 +23,14 @@ mod status;
 mod utils;
 mod judge;
 mod dump;
+mod image;
+mod doctor;
+mod testcase;
+mod report;
+mod manifest;
+mod stage;
+mod rubric;
+mod snippet;
 
 #[derive(opt::StructOpt, Debug)]
 struct Opt {
@@ -42,7 +52,8 @@ struct Opt {
 enum SubCommand {
     #[structopt(about = "Initialize grading")]
     Init {
-        #[structopt(short, long, help = "Path to the root image")]
+        #[structopt(short, long,
+        help = "Path to the root image: squashfs, rootfs directory, docker archive or OCI layout")]
         base_image: PathBuf
     },
     #[structopt(about = "Check status")]
@@ -85,10 +96,32 @@ enum SubCommand {
         #[structopt(subcommand)]
         subcommand: JudgeCommand
     },
+    #[structopt(about = "Root image management")]
+    Image {
+        #[structopt(subcommand)]
+        subcommand: image::ImageCommand
+    },
+    #[structopt(about = "Rubrics of the manual grades")]
+    Rubric {
+        #[structopt(subcommand)]
+        subcommand: rubric::RubricCommand
+    },
+    #[structopt(about = "Comment snippets of the projects")]
+    Snippet {
+        #[structopt(subcommand)]
+        subcommand: snippet::SnippetCommand
+    },
+    #[structopt(about = "Check prerequisites and clean up containers left by crashed runs")]
+    Doctor {
+        #[structopt(long, help = "Only report, do not unmount or remove anything")]
+        dry_run: bool,
+    },
     #[structopt(about = "Dump grades")]
     Dump {
         #[structopt(long, short, help = "Path to the output file")]
-        target: String
+        target: String,
+        #[structopt(long, help = "Also copy the artifacts of all grades into this directory")]
+        artifacts: Option<PathBuf>,
     },
 }
 
@@ -131,10 +164,128 @@ enum CleanCommand {
 enum ProjectCommand {
     #[structopt(about = "Add a new template")]
     Add {
-        #[structopt(short, long, help = "Path to the project template")]
+        #[structopt(short, long, help = "Path to the project template, its project.toml gives the defaults of the \
+        options below")]
         path: PathBuf,
         #[structopt(short, long, help = "Project identifier")]
         name: String,
+        #[structopt(short, long, help = "Container backend used to judge the project",
+        possible_values = container::BACKENDS)]
+        backend: Option<container::Backend>,
+        #[structopt(long, help = "X11 access of the project, forward the grader's display if available by default",
+        possible_values = container::DISPLAY_MODES)]
+        display: Option<container::DisplayMode>,
+        #[structopt(long, help = "Root image of the project, fall back to the global base image if not given")]
+        image: Option<PathBuf>,
+        #[structopt(long, help = "Where the submission is mounted inside the container [default: /project/src]")]
+        submission_mount: Option<String>,
+        #[structopt(long, help = "Comma separated globs locating the submission root in the student directory, \
+        the first one matching a single directory is used [default: src,*/src]")]
+        submission_root: Option<String>,
+        #[structopt(long, help = "Comma separated globs of files to keep after run.sh, relative to /project")]
+        artifacts: Option<String>,
+        #[structopt(long, help = "How test case outputs are checked: exact, whitespace, \
+        float[:abs=EPS][,rel=EPS], regex or checker:PATH [default: exact]")]
+        comparator: Option<comparator::Comparator>,
+        #[structopt(long, help = "Where the results of run.sh come from without test cases: json, junit:PATH, \
+        tap or tap:PATH [default: json]")]
+        result_format: Option<report::ResultFormat>,
+        #[structopt(long, help = "Comma separated GLOB=POINTS pairs for the tests of JUnit and TAP reports, \
+        unmatched tests are worth 1 point")]
+        test_points: Option<report::Points>,
+        #[structopt(long, help = "Maximum auto grade, higher results are capped")]
+        max_auto_grade: Option<f64>,
+        #[structopt(long, help = "Maximum manual grade, higher grades are rejected")]
+        max_manual_grade: Option<f64>,
+    },
+    #[structopt(about = "Change the container backend of the template")]
+    SetBackend {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(short, long, help = "Container backend, fall back to the default if not given",
+        possible_values = container::BACKENDS)]
+        backend: Option<container::Backend>,
+    },
+    #[structopt(about = "Change the X11 access of the template")]
+    SetDisplay {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "X11 access, fall back to the default if not given",
+        possible_values = container::DISPLAY_MODES)]
+        display: Option<container::DisplayMode>,
+    },
+    #[structopt(about = "Change the root image of the template")]
+    SetImage {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "Root image, fall back to the global base image if not given")]
+        image: Option<PathBuf>,
+    },
+    #[structopt(about = "Change the submission layout of the template, unset values fall back to the defaults")]
+    SetLayout {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "Where the submission is mounted inside the container [default: /project/src]")]
+        mount: Option<String>,
+        #[structopt(long, help = "Comma separated globs locating the submission root [default: src,*/src]")]
+        root: Option<String>,
+    },
+    #[structopt(about = "Change the artifact globs of the template, relative to /project")]
+    SetArtifacts {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "Comma separated globs, nothing is kept if not given")]
+        artifacts: Option<String>,
+    },
+    #[structopt(about = "Change how the test case outputs of the template are checked")]
+    SetComparator {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "exact, whitespace, float[:abs=EPS][,rel=EPS], regex or checker:PATH, \
+        fall back to exact if not given")]
+        comparator: Option<comparator::Comparator>,
+    },
+    #[structopt(about = "Change where the template takes the results of run.sh from")]
+    SetResultFormat {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "json, junit:PATH, tap or tap:PATH, fall back to json if not given")]
+        format: Option<report::ResultFormat>,
+        #[structopt(long, help = "Comma separated GLOB=POINTS pairs, every test is worth 1 point if not given")]
+        points: Option<report::Points>,
+    },
+    #[structopt(about = "Change the maximum grades of the template, unset maximums are removed")]
+    SetMaxGrade {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "Maximum auto grade, fall back to the one reported by run.sh if not given")]
+        auto: Option<f64>,
+        #[structopt(long, help = "Maximum manual grade")]
+        manual: Option<f64>,
+    },
+    #[structopt(about = "Change the time limits (in seconds) of the template, unset limits are removed")]
+    SetLimits {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "Wall clock limit of build.sh")]
+        build_time: Option<i32>,
+        #[structopt(long, help = "Wall clock limit of run.sh")]
+        run_time: Option<i32>,
+        #[structopt(long, help = "CPU time limit of build.sh")]
+        build_cpu: Option<i32>,
+        #[structopt(long, help = "CPU time limit of run.sh")]
+        run_cpu: Option<i32>,
+    },
+    #[structopt(about = "Change the resource quota of the template, unset limits are removed")]
+    SetQuota {
+        #[structopt(short, long, help = "The id to change")]
+        id: i32,
+        #[structopt(long, help = "Memory limit in MiB")]
+        memory: Option<i32>,
+        #[structopt(long, help = "Maximum number of processes")]
+        pids: Option<i32>,
+        #[structopt(long, help = "Size limit of the writable layers in MiB")]
+        disk: Option<i32>,
     },
     #[structopt(about = "Remove the template")]
     Remove {
@@ -182,10 +333,8 @@ fn main() {
         .unwrap_with_log();
     match &opt.subcommand {
         SubCommand::Init { base_image } => {
-            base_image
-                .to_str()
-                .ok_or(anyhow::anyhow!("invalid image path"))
-                .and_then_into(|path| model::Configuration::initialize(&conn, path))
+            image::locate(base_image)
+                .and_then(|path| model::Configuration::initialize(&conn, &path))
                 .and_then_into(|_| {
                     std::fs::read_dir(&opt.workdir)
                 })
@@ -219,48 +368,11 @@ fn main() {
         }
         SubCommand::Commit => {
             model::Configuration::get_global(&conn)
-                .and_then(|mut conf| {
-                    if conf.current_project.is_none() {
-                        Err(anyhow::anyhow!("no current grading project"))
-                    } else if conf.current_student.is_none() {
-                        Err(anyhow::anyhow!("not current grading student"))
-                    } else {
-                        use crate::schema::grade::dsl as g;
-                        let grade: QueryResult<crate::model::Grade> = g::grade
-                            .filter(g::student_id
-                                .eq(conf.current_student.unwrap())
-                                .and(g::project_id.eq(conf.current_project.unwrap())))
-                            .first::<crate::model::Grade>(&conn);
-                        let grade = model::ChangeGrade {
-                            id: match grade {
-                                Ok(x) => Some(x.id),
-                                _ => None
-                            },
-                            student_id: conf.current_student.take(),
-                            project_id: conf.current_project.clone(),
-                            manual_grade: conf.manual_grade.take(),
-                            auto_grade: conf.auto_grade.take(),
-                            comment: conf.comment.take(),
-                            compile_stdout: conf.compile_stdout.take(),
-                            compile_stderr: conf.compile_stderr.take(),
-                            compile_return: conf.compile_return.take(),
-                            run_stdout: conf.run_stdout.take(),
-                            run_stderr: conf.run_stderr.take(),
-                            run_return: conf.run_return.take(),
-                        };
-
-                        diesel::replace_into(schema::grade::table)
-                            .values(grade)
-                            .execute(&conn)
-                            .and_then_into(|_| {
-                                conf.store(&conn)
-                            })
-                    }
-                })
+                .and_then(|mut conf| conf.commit(&conn))
                 .unwrap_with_log();
         }
-        SubCommand::Dump { target } => {
-            dump::dump(&conn, target);
+        SubCommand::Dump { target, artifacts } => {
+            dump::dump(&conn, target, artifacts.as_ref().map(AsRef::as_ref));
         }
         SubCommand::Clean { subcommand } => {
             if subcommand == &CleanCommand::Config {
@@ -271,21 +383,21 @@ fn main() {
                 model::Configuration::get_global(&conn)
                     .and_then(|mut conf| {
                         if subcommand == &CleanCommand::Result || subcommand >= &CleanCommand::Student {
-                            conf.compile_return.take();
-                            conf.compile_stderr.take();
-                            conf.compile_stdout.take();
-                            conf.run_return.take();
-                            conf.run_stderr.take();
-                            conf.run_stdout.take();
+                            conf.clean_result();
+                            artifact::clean(&conn)?;
+                            testcase::clean(&conn)?;
+                            stage::clean(&conn)?;
                         }
                         if subcommand == &CleanCommand::Comment || subcommand >= &CleanCommand::Student {
                             conf.comment.take();
                         }
                         if subcommand == &CleanCommand::AutoGrade || subcommand >= &CleanCommand::Student {
                             conf.auto_grade.take();
+                            conf.max_auto_grade.take();
                         }
                         if subcommand == &CleanCommand::ManualGrade || subcommand >= &CleanCommand::Student {
                             conf.manual_grade.take();
+                            rubric::clean(&conn)?;
                         }
                         if subcommand >= &CleanCommand::Student {
                             conf.current_student.take();
@@ -307,17 +419,134 @@ fn main() {
                         .execute(&conn)
                         .map_err(Into::into)
                 }
-                ProjectCommand::Add { path, name } => {
-                    path.to_str()
-                        .ok_or(anyhow::anyhow!("invalid path"))
-                        .and_then_into(|x| {
-                            diesel::insert_into(schema::project::table)
-                                .values(model::ChangeProject {
-                                    path: Some(x),
-                                    name: Some(name),
-                                })
-                                .execute(&conn)
-                        })
+                ProjectCommand::Add {
+                    path, name, backend, display, image, submission_mount, submission_root, artifacts, comparator,
+                    result_format, test_points, max_auto_grade, max_manual_grade
+                } => {
+                    // a broken manifest is rejected, its settings fill in what is not given here
+                    manifest::Manifest::load(path)
+                        .map(Option::unwrap_or_default)
+                        .and_then(|manifest| submission_mount.as_ref()
+                            .map(|x| judge::check_mount(x))
+                            .unwrap_or(Ok(()))
+                            .map(|_| manifest))
+                        .and_then(|manifest| max_auto_grade.iter()
+                            .chain(max_manual_grade)
+                            .try_for_each(|x| check_grade(*x, None))
+                            .map(|_| manifest))
+                        .and_then(|manifest| image.as_ref()
+                            .map(|x| image::locate(x).map(Some))
+                            .unwrap_or(Ok(None))
+                            .map(|image| (manifest, image)))
+                        .and_then(|(manifest, image)| path.to_str()
+                            .ok_or(anyhow::anyhow!("invalid path"))
+                            .and_then_into(|x| {
+                                diesel::insert_into(schema::project::table)
+                                    .values(model::ChangeProject {
+                                        path: Some(x),
+                                        name: Some(name),
+                                        backend: backend.as_ref().map(ToString::to_string),
+                                        display: display.as_ref().map(ToString::to_string),
+                                        image,
+                                        submission_mount: submission_mount.clone().or(manifest.submission_mount),
+                                        submission_root: submission_root.clone(),
+                                        artifacts: artifacts.clone(),
+                                        comparator: comparator.as_ref().map(ToString::to_string),
+                                        result_format: result_format.as_ref()
+                                            .map(ToString::to_string)
+                                            .or(manifest.result_format),
+                                        test_points: test_points.as_ref()
+                                            .map(ToString::to_string)
+                                            .or(manifest.test_points),
+                                        max_auto_grade: max_auto_grade.or(manifest.max_auto_grade),
+                                        max_manual_grade: max_manual_grade.or(manifest.max_manual_grade),
+                                    })
+                                    .execute(&conn)
+                            }))
+                }
+                ProjectCommand::SetBackend { id: target_id, backend: target } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set(backend.eq(target.as_ref().map(ToString::to_string)))
+                        .execute(&conn)
+                        .map_err(Into::into)
+                }
+                ProjectCommand::SetDisplay { id: target_id, display: target } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set(display.eq(target.as_ref().map(ToString::to_string)))
+                        .execute(&conn)
+                        .map_err(Into::into)
+                }
+                ProjectCommand::SetImage { id: target_id, image: target } => {
+                    use schema::project::dsl::*;
+                    target.as_ref()
+                        .map(|x| crate::image::locate(x).map(Some))
+                        .unwrap_or(Ok(None))
+                        .and_then_into(|target| diesel::update(project.find(target_id))
+                            .set(image.eq(target))
+                            .execute(&conn))
+                }
+                ProjectCommand::SetLayout { id: target_id, mount, root } => {
+                    use schema::project::dsl::*;
+                    mount.as_ref()
+                        .map(|x| judge::check_mount(x))
+                        .unwrap_or(Ok(()))
+                        .and_then_into(|_| diesel::update(project.find(target_id))
+                            .set((submission_mount.eq(mount),
+                                  submission_root.eq(root)))
+                            .execute(&conn))
+                }
+                ProjectCommand::SetArtifacts { id: target_id, artifacts: target } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set(artifacts.eq(target))
+                        .execute(&conn)
+                        .map_err(Into::into)
+                }
+                ProjectCommand::SetComparator { id: target_id, comparator: target } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set(comparator.eq(target.as_ref().map(ToString::to_string)))
+                        .execute(&conn)
+                        .map_err(Into::into)
+                }
+                ProjectCommand::SetResultFormat { id: target_id, format, points } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set((result_format.eq(format.as_ref().map(ToString::to_string)),
+                              test_points.eq(points.as_ref().map(ToString::to_string))))
+                        .execute(&conn)
+                        .map_err(Into::into)
+                }
+                ProjectCommand::SetMaxGrade { id: target_id, auto, manual } => {
+                    use schema::project::dsl::*;
+                    auto.iter()
+                        .chain(manual)
+                        .try_for_each(|x| check_grade(*x, None))
+                        .and_then_into(|_| diesel::update(project.find(target_id))
+                            .set((max_auto_grade.eq(auto),
+                                  max_manual_grade.eq(manual)))
+                            .execute(&conn))
+                }
+                ProjectCommand::SetLimits { id: target_id, build_time, run_time, build_cpu, run_cpu } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set((build_time_limit.eq(build_time),
+                              run_time_limit.eq(run_time),
+                              build_cpu_limit.eq(build_cpu),
+                              run_cpu_limit.eq(run_cpu)))
+                        .execute(&conn)
+                        .map_err(Into::into)
+                }
+                ProjectCommand::SetQuota { id: target_id, memory, pids, disk } => {
+                    use schema::project::dsl::*;
+                    diesel::update(project.find(target_id))
+                        .set((memory_limit.eq(memory),
+                              pids_limit.eq(pids),
+                              disk_limit.eq(disk)))
+                        .execute(&conn)
+                        .map_err(Into::into)
                 }
             };
             match sql_result {
@@ -418,6 +647,18 @@ fn main() {
         SubCommand::Judge { subcommand } => {
             judge::handle(&conn, subcommand)
         }
+        SubCommand::Doctor { dry_run } => {
+            doctor::doctor(*dry_run)
+        }
+        SubCommand::Image { subcommand } => {
+            image::handle(&conn, subcommand)
+        }
+        SubCommand::Rubric { subcommand } => {
+            rubric::handle(&conn, subcommand)
+        }
+        SubCommand::Snippet { subcommand } => {
+            snippet::handle(&conn, subcommand)
+        }
         SubCommand::Status { subcommand } => {
             status::handle(subcommand, &conn)
         }
@@ -432,6 +673,11 @@ fn main() {
                                 log::info!("deleted {} items", x)
                             })
                             .map_err(Into::into)
+                            .and_then(|_| artifact::remove(&conn, None))
+                            .and_then(|_| testcase::remove(&conn, None))
+                            .and_then(|_| stage::remove(&conn, None))
+                            .and_then(|_| rubric::remove(&conn, None))
+                            .map(|_| ())
                     } else {
                         Err(anyhow::anyhow!("operation canceled"))
                     })
@@ -448,6 +694,11 @@ fn main() {
                                 log::info!("deleted {} items", x)
                             })
                             .map_err(Into::into)
+                            .and_then(|_| artifact::remove(&conn, Some(id)))
+                            .and_then(|_| testcase::remove(&conn, Some(id)))
+                            .and_then(|_| stage::remove(&conn, Some(id)))
+                            .and_then(|_| rubric::remove(&conn, Some(id)))
+                            .map(|_| ())
                     } else {
                         Err(anyhow::anyhow!("operation canceled"))
                     })
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::*;
+
+use crate::report::{Points, ResultFormat};
+
+pub const MANIFEST_FILE: &str = "project.toml";
+
+/// Stages every pipeline has, `build` fills the compile results and `run` the run results and
+/// the grade of the tests
+pub const BUILD: &str = "build";
+pub const RUN: &str = "run";
+
+/// # Project Manifest
+/// An optional `project.toml` in the project template, every key may be left out:
+/// ```toml
+/// submission_mount = "/project/src"
+/// result_format = "junit:report.xml"
+/// test_points = "test_io*=2"
+/// max_auto_grade = 100
+/// max_manual_grade = 20
+///
+/// [env]
+/// CFLAGS = "-O2 -Wall"
+///
+/// [[stage]]
+/// name = "style"
+/// command = "clang-format --dry-run -Werror src/*.c"
+/// abort_on_failure = false
+/// points = 5
+///
+/// [[stage]]
+/// name = "build"
+/// command = "make"
+/// time_limit = 60
+/// cpu_limit = 30
+/// abort_on_failure = true
+/// ```
+/// The top level keys are the defaults of `project add`, stages and environment variables are
+/// read again whenever the project is judged. Stages run in the given order, a missing `build`
+/// stage runs `sh build.sh` first and a missing `run` stage runs `sh run.sh` after `build`.
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default, rename = "stage")]
+    pub stages: Vec<Stage>,
+    pub submission_mount: Option<String>,
+    pub result_format: Option<String>,
+    pub test_points: Option<String>,
+    pub max_auto_grade: Option<f64>,
+    pub max_manual_grade: Option<f64>,
+    /// exported to every stage
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Stage {
+    pub name: String,
+    /// run by `sh -c` at `/project`, arguments such as the test case directory follow as `$1`...
+    pub command: String,
+    /// wall clock limit in seconds, overrides the one of the project
+    pub time_limit: Option<i32>,
+    /// CPU time limit in seconds, overrides the one of the project
+    pub cpu_limit: Option<i32>,
+    /// stop judging if the stage fails, otherwise the failure is only recorded
+    #[serde(default = "abort_by_default")]
+    pub abort_on_failure: bool,
+    /// added to the auto grade if the stage succeeds, or the fraction `x/y` of them if the last
+    /// line of its stdout is `[RESULT] x/y`; not allowed for `run`, which is graded by its tests
+    pub points: Option<f64>,
+}
+
+fn abort_by_default() -> bool {
+    true
+}
+
+fn check_env(key: &str) -> Result<()> {
+    let mut chars = key.chars();
+    let valid = chars.next().map(|x| x.is_ascii_alphabetic() || x == '_').unwrap_or(false)
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_');
+    if !valid {
+        Err(anyhow!("invalid environment variable name: {}", key))
+    } else if key.starts_with("HELPER_") {
+        Err(anyhow!("environment variable {} is reserved for the helper", key))
+    } else {
+        Ok(())
+    }
+}
+
+impl Manifest {
+    pub fn parse(content: &str) -> Result<Self> {
+        let manifest: Manifest = toml::from_str(content)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// The manifest of the template at `project`, `None` if it has none
+    pub fn load(project: &Path) -> Result<Option<Self>> {
+        let path = project.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        std::fs::read_to_string(&path)
+            .map_err(Into::into)
+            .and_then(|x| Self::parse(&x))
+            .map(Some)
+            .map_err(|e| anyhow!("invalid manifest {}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (n, stage) in self.stages.iter().enumerate() {
+            let valid = !stage.name.is_empty()
+                && stage.name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-');
+            if !valid {
+                return Err(anyhow!("invalid stage name {:?}, use letters, digits, _ and -", stage.name));
+            }
+            if self.stages[..n].iter().any(|x| x.name == stage.name) {
+                return Err(anyhow!("stage {} is declared twice", stage.name));
+            }
+            if stage.name == BUILD && self.position(RUN).map(|x| x < n).unwrap_or(false) {
+                return Err(anyhow!("stage {} must come before {}", BUILD, RUN));
+            }
+            match stage.points {
+                Some(_) if stage.name == RUN => return Err(anyhow!("stage {} is graded by its tests, \
+                it cannot have points", RUN)),
+                Some(x) if !x.is_finite() || x <= 0.0 => return Err(anyhow!("points of stage {} must be positive",
+                                                                            stage.name)),
+                _ => ()
+            }
+            if stage.command.trim().is_empty() {
+                return Err(anyhow!("stage {} has no command", stage.name));
+            }
+            if stage.time_limit.iter().chain(&stage.cpu_limit).any(|x| *x <= 0) {
+                return Err(anyhow!("time limits of stage {} must be positive", stage.name));
+            }
+        }
+        if let Some(x) = &self.submission_mount {
+            crate::judge::check_mount(x)?;
+        }
+        if let Some(x) = &self.result_format {
+            x.parse::<ResultFormat>()?;
+        }
+        if let Some(x) = &self.test_points {
+            x.parse::<Points>()?;
+        }
+        for x in self.max_auto_grade.iter().chain(&self.max_manual_grade) {
+            crate::utils::check_grade(*x, None)?;
+        }
+        self.env.keys().try_for_each(|x| check_env(x))
+    }
+
+    pub fn stage(&self, name: &str) -> Option<&Stage> {
+        self.stages.iter().find(|x| x.name == name)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.stages.iter().position(|x| x.name == name)
+    }
+
+    /// Names of the stages in the order they run, including the implicit `build` and `run`
+    pub fn pipeline(&self) -> Vec<&str> {
+        let mut names = self.stages.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        if !names.contains(&BUILD) {
+            names.insert(0, BUILD);
+        }
+        if !names.contains(&RUN) {
+            let build = names.iter().position(|x| *x == BUILD).unwrap_or(0);
+            names.insert(build + 1, RUN);
+        }
+        names
+    }
+
+    pub fn env(&self) -> Vec<(String, String)> {
+        self.env.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let manifest = Manifest::parse(r#"
+max_auto_grade = 100
+result_format = "tap"
+
+[env]
+CFLAGS = "-O2"
+
+[[stage]]
+name = "build"
+command = "make"
+time_limit = 60
+points = 1
+
+[[stage]]
+name = "run"
+command = "./main"
+abort_on_failure = false
+"#)?;
+        assert_eq!(manifest.max_auto_grade, Some(100.0));
+        assert_eq!(manifest.env(), vec![(String::from("CFLAGS"), String::from("-O2"))]);
+        assert_eq!(manifest.stage("build").map(|x| (x.command.as_str(), x.time_limit, x.abort_on_failure)),
+                   Some(("make", Some(60), true)));
+        assert_eq!(manifest.stage("run").map(|x| x.abort_on_failure), Some(false));
+        assert_eq!(manifest.pipeline(), vec!["build", "run"]);
+        assert_eq!(Manifest::parse("")?.pipeline(), vec!["build", "run"]);
+        let manifest = Manifest::parse("[[stage]]\nname = \"style\"\ncommand = \"lint\"\npoints = 2\n\
+        [[stage]]\nname = \"build\"\ncommand = \"make\"\n[[stage]]\nname = \"clean-up\"\ncommand = \"rm -rf /tmp/x\"")?;
+        assert_eq!(manifest.pipeline(), vec!["style", "build", "run", "clean-up"]);
+        for i in &[
+            "max_auto_grade = -1",
+            "submission_mount = \"/project\"",
+            "result_format = \"xml\"",
+            "unknown = 1",
+            "[env]\nHELPER_RESULT = \"x\"",
+            "[env]\n\"1X\" = \"x\"",
+            "[[stage]]\nname = \"lint it\"\ncommand = \"lint\"",
+            "[[stage]]\nname = \"lint\"\ncommand = \"lint\"\npoints = 0",
+            "[[stage]]\nname = \"run\"\ncommand = \"./main\"\npoints = 1",
+            "[[stage]]\nname = \"build\"\ncommand = \" \"",
+            "[[stage]]\nname = \"build\"\ncommand = \"make\"\ntime_limit = 0",
+            "[[stage]]\nname = \"run\"\ncommand = \"a\"\n[[stage]]\nname = \"build\"\ncommand = \"b\"",
+            "[[stage]]\nname = \"run\"\ncommand = \"a\"\n[[stage]]\nname = \"run\"\ncommand = \"b\"",
+        ] {
+            assert!(Manifest::parse(i).is_err(), "{}", i);
+        }
+        Ok(())
+    }
+}
@@ -33,6 +33,24 @@ pub struct Project {
     pub id: i32,
     pub path: String,
     pub name: String,
+    pub backend: Option<String>,
+    pub build_time_limit: Option<i32>,
+    pub run_time_limit: Option<i32>,
+    pub build_cpu_limit: Option<i32>,
+    pub run_cpu_limit: Option<i32>,
+    pub memory_limit: Option<i32>,
+    pub pids_limit: Option<i32>,
+    pub disk_limit: Option<i32>,
+    pub display: Option<String>,
+    pub image: Option<String>,
+    pub submission_mount: Option<String>,
+    pub submission_root: Option<String>,
+    pub artifacts: Option<String>,
+    pub comparator: Option<String>,
+    pub result_format: Option<String>,
+    pub test_points: Option<String>,
+    pub max_auto_grade: Option<f64>,
+    pub max_manual_grade: Option<f64>,
 }
 
 #[derive(diesel::Queryable,
@@ -49,15 +67,34 @@ pub struct Grade {
     pub id: i32,
     pub student_id: i32,
     pub project_id: i32,
-    pub manual_grade: i32,
-    pub auto_grade: i32,
+    pub manual_grade: f64,
+    pub auto_grade: f64,
     pub comment: String,
     pub compile_stdout: String,
     pub compile_stderr: String,
     pub compile_return: i32,
     pub run_stdout: String,
     pub run_stderr: String,
-    pub run_return: i32
+    pub run_return: i32,
+    pub compile_outcome: String,
+    pub run_outcome: String,
+    pub image_hash: Option<String>,
+    pub compile_signal: Option<i32>,
+    pub compile_core_dumped: Option<bool>,
+    pub compile_wall_time: Option<i32>,
+    pub compile_cpu_time: Option<i32>,
+    pub compile_peak_memory: Option<i32>,
+    pub run_signal: Option<i32>,
+    pub run_core_dumped: Option<bool>,
+    pub run_wall_time: Option<i32>,
+    pub run_cpu_time: Option<i32>,
+    pub run_peak_memory: Option<i32>,
+    /// the maximum reported by run.sh or the test cases
+    pub max_auto_grade: Option<f64>,
+    /// `sha256:` digest of the submission directory that was judged
+    pub submission_hash: Option<String>,
+    /// `sha256:` digest of the project template, scripts and test cases included
+    pub template_hash: Option<String>,
 }
 
 #[derive(diesel::Queryable,
@@ -72,8 +109,8 @@ pub struct Configuration {
     pub id: i32,
     pub current_student: Option<i32>,
     pub current_project: Option<i32>,
-    pub auto_grade: Option<i32>,
-    pub manual_grade: Option<i32>,
+    pub auto_grade: Option<f64>,
+    pub manual_grade: Option<f64>,
     pub comment: Option<String>,
     pub base_image: String,
     pub compile_stdout: Option<String>,
@@ -81,7 +118,26 @@ pub struct Configuration {
     pub compile_return: Option<i32>,
     pub run_stdout: Option<String>,
     pub run_stderr: Option<String>,
-    pub run_return: Option<i32>
+    pub run_return: Option<i32>,
+    pub compile_outcome: Option<String>,
+    pub run_outcome: Option<String>,
+    pub image_hash: Option<String>,
+    pub compile_signal: Option<i32>,
+    pub compile_core_dumped: Option<bool>,
+    pub compile_wall_time: Option<i32>,
+    pub compile_cpu_time: Option<i32>,
+    pub compile_peak_memory: Option<i32>,
+    pub run_signal: Option<i32>,
+    pub run_core_dumped: Option<bool>,
+    pub run_wall_time: Option<i32>,
+    pub run_cpu_time: Option<i32>,
+    pub run_peak_memory: Option<i32>,
+    /// the maximum reported by run.sh or the test cases
+    pub max_auto_grade: Option<f64>,
+    /// `sha256:` digest of the submission directory that was judged
+    pub submission_hash: Option<String>,
+    /// `sha256:` digest of the project template, scripts and test cases included
+    pub template_hash: Option<String>,
 }
 
 #[derive(Insertable, Default, Debug, AsChangeset)]
@@ -90,8 +146,8 @@ pub struct ChangeConfig<'a> {
     pub id: i32,
     pub current_student: Option<i32>,
     pub current_project: Option<i32>,
-    pub auto_grade: Option<i32>,
-    pub manual_grade: Option<i32>,
+    pub auto_grade: Option<f64>,
+    pub manual_grade: Option<f64>,
     pub comment: Option<&'a str>,
     pub base_image: Option<&'a str>,
     pub compile_stdout: Option<String>,
@@ -99,7 +155,23 @@ pub struct ChangeConfig<'a> {
     pub compile_return: Option<i32>,
     pub run_stdout: Option<String>,
     pub run_stderr: Option<String>,
-    pub run_return: Option<i32>
+    pub run_return: Option<i32>,
+    pub compile_outcome: Option<String>,
+    pub run_outcome: Option<String>,
+    pub image_hash: Option<String>,
+    pub compile_signal: Option<i32>,
+    pub compile_core_dumped: Option<bool>,
+    pub compile_wall_time: Option<i32>,
+    pub compile_cpu_time: Option<i32>,
+    pub compile_peak_memory: Option<i32>,
+    pub run_signal: Option<i32>,
+    pub run_core_dumped: Option<bool>,
+    pub run_wall_time: Option<i32>,
+    pub run_cpu_time: Option<i32>,
+    pub run_peak_memory: Option<i32>,
+    pub max_auto_grade: Option<f64>,
+    pub submission_hash: Option<String>,
+    pub template_hash: Option<String>,
 }
 
 #[derive(Insertable, Default, Debug, AsChangeset)]
@@ -108,22 +180,268 @@ pub struct ChangeGrade {
     pub id: Option<i32>,
     pub student_id: Option<i32>,
     pub project_id: Option<i32>,
-    pub manual_grade: Option<i32>,
-    pub auto_grade: Option<i32>,
+    pub manual_grade: Option<f64>,
+    pub auto_grade: Option<f64>,
     pub comment: Option<String>,
     pub compile_stdout: Option<String>,
     pub compile_stderr: Option<String>,
     pub compile_return: Option<i32>,
     pub run_stdout: Option<String>,
     pub run_stderr: Option<String>,
-    pub run_return: Option<i32>
+    pub run_return: Option<i32>,
+    pub compile_outcome: Option<String>,
+    pub run_outcome: Option<String>,
+    pub image_hash: Option<String>,
+    pub compile_signal: Option<i32>,
+    pub compile_core_dumped: Option<bool>,
+    pub compile_wall_time: Option<i32>,
+    pub compile_cpu_time: Option<i32>,
+    pub compile_peak_memory: Option<i32>,
+    pub run_signal: Option<i32>,
+    pub run_core_dumped: Option<bool>,
+    pub run_wall_time: Option<i32>,
+    pub run_cpu_time: Option<i32>,
+    pub run_peak_memory: Option<i32>,
+    pub max_auto_grade: Option<f64>,
+    pub submission_hash: Option<String>,
+    pub template_hash: Option<String>,
 }
 
 #[derive(Insertable, Default, Debug, AsChangeset)]
 #[table_name="project"]
 pub struct ChangeProject<'a> {
     pub path: Option<&'a str>,
-    pub name: Option<&'a str>
+    pub name: Option<&'a str>,
+    pub backend: Option<String>,
+    pub display: Option<String>,
+    pub image: Option<String>,
+    pub submission_mount: Option<String>,
+    pub submission_root: Option<String>,
+    pub artifacts: Option<String>,
+    pub comparator: Option<String>,
+    pub result_format: Option<String>,
+    pub test_points: Option<String>,
+    pub max_auto_grade: Option<f64>,
+    pub max_manual_grade: Option<f64>,
+}
+
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="image"]
+pub struct Image {
+    pub id: i32,
+    pub path: String,
+    pub format: String,
+    pub hash: String,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="image"]
+pub struct ChangeImage<'a> {
+    pub path: Option<&'a str>,
+    pub format: Option<String>,
+    pub hash: Option<String>,
+}
+
+/// A file captured from the writable layers after `run.sh`, `grade_id` is unset until commit
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="artifact"]
+pub struct Artifact {
+    pub id: i32,
+    pub grade_id: Option<i32>,
+    pub path: String,
+    pub location: String,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="artifact"]
+pub struct ChangeArtifact<'a> {
+    pub grade_id: Option<i32>,
+    pub path: Option<&'a str>,
+    pub location: Option<&'a str>,
+}
+
+/// The outcome of one test case, `grade_id` is unset until commit
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="case_result"]
+pub struct CaseResult {
+    pub id: i32,
+    pub grade_id: Option<i32>,
+    pub name: String,
+    pub verdict: String,
+    pub weight: f64,
+    pub score: f64,
+    pub return_code: Option<i32>,
+    /// milliseconds
+    pub wall_time: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// feedback reported by the test harness
+    pub message: Option<String>,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="case_result"]
+pub struct ChangeCaseResult {
+    pub grade_id: Option<i32>,
+    pub name: Option<String>,
+    pub verdict: Option<String>,
+    pub weight: Option<f64>,
+    pub score: Option<f64>,
+    pub return_code: Option<i32>,
+    pub wall_time: Option<i32>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub message: Option<String>,
+}
+
+/// The outcome of one stage of the pipeline, `grade_id` is unset until commit
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="stage_result"]
+pub struct StageResult {
+    pub id: i32,
+    pub grade_id: Option<i32>,
+    /// order of the stage in the pipeline, starting at 0
+    pub position: i32,
+    pub name: String,
+    pub outcome: String,
+    pub return_code: Option<i32>,
+    /// milliseconds
+    pub wall_time: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// points earned, `None` if the stage is not graded
+    pub score: Option<f64>,
+    pub max_score: Option<f64>,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="stage_result"]
+pub struct ChangeStageResult {
+    pub grade_id: Option<i32>,
+    pub position: Option<i32>,
+    pub name: Option<String>,
+    pub outcome: Option<String>,
+    pub return_code: Option<i32>,
+    pub wall_time: Option<i32>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub score: Option<f64>,
+    pub max_score: Option<f64>,
+}
+
+/// A part of the manual grade of a project, worth `max_points` minus the selected deductions
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="criterion"]
+pub struct Criterion {
+    pub id: i32,
+    pub project_id: i32,
+    pub name: String,
+    pub max_points: f64,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="criterion"]
+pub struct ChangeCriterion<'a> {
+    pub project_id: Option<i32>,
+    pub name: Option<&'a str>,
+    pub max_points: Option<f64>,
+}
+
+/// A predefined reason to take points off a criterion
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="deduction"]
+pub struct Deduction {
+    pub id: i32,
+    pub criterion_id: i32,
+    pub description: String,
+    pub points: f64,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="deduction"]
+pub struct ChangeDeduction<'a> {
+    pub criterion_id: Option<i32>,
+    pub description: Option<&'a str>,
+    pub points: Option<f64>,
+}
+
+/// A deduction chosen by the grader, `grade_id` is unset until commit. A row without a
+/// deduction marks the criterion as graded.
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="rubric_selection"]
+pub struct RubricSelection {
+    pub id: i32,
+    pub grade_id: Option<i32>,
+    pub criterion_id: i32,
+    pub deduction_id: Option<i32>,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="rubric_selection"]
+pub struct ChangeRubricSelection {
+    pub grade_id: Option<i32>,
+    pub criterion_id: Option<i32>,
+    pub deduction_id: Option<i32>,
+}
+
+/// A canned comment of a project, see `snippet::render` for its placeholders
+#[derive(diesel::Queryable,
+    diesel::Identifiable,
+    Debug,
+    Tablefy,
+    serde::Serialize,
+    serde::Deserialize)]
+#[table_name="snippet"]
+pub struct Snippet {
+    pub id: i32,
+    pub project_id: i32,
+    pub key: String,
+    pub body: String,
+    /// how many times it was put into a comment
+    pub uses: i32,
+}
+
+#[derive(Insertable, Default, Debug, AsChangeset)]
+#[table_name="snippet"]
+pub struct ChangeSnippet<'a> {
+    pub project_id: Option<i32>,
+    pub key: Option<&'a str>,
+    pub body: Option<&'a str>,
 }
 
 #[derive(Insertable, Default, Debug, AsChangeset)]
@@ -149,11 +467,119 @@ impl Configuration {
                 compile_return: None,
                 run_stdout: None,
                 run_stderr: None,
-                run_return: None
+                run_return: None,
+                compile_outcome: None,
+                run_outcome: None,
+                image_hash: None,
+                compile_signal: None,
+                compile_core_dumped: None,
+                compile_wall_time: None,
+                compile_cpu_time: None,
+                compile_peak_memory: None,
+                run_signal: None,
+                run_core_dumped: None,
+                run_wall_time: None,
+                run_cpu_time: None,
+                run_peak_memory: None,
+                max_auto_grade: None,
+                submission_hash: None,
+                template_hash: None,
             })
             .execute(conn)?;
         Ok(())
     }
+    /// Turn the current result into the grade of the current student and project
+    pub fn commit(&mut self, conn: &diesel::SqliteConnection) -> Result<usize> {
+        use diesel::prelude::*;
+        use crate::utils::AndThenInto;
+        if self.current_project.is_none() {
+            Err(anyhow::anyhow!("no current grading project"))
+        } else if self.current_student.is_none() {
+            Err(anyhow::anyhow!("not current grading student"))
+        } else {
+            use crate::schema::grade::dsl as g;
+            let (student_id, project_id) = (self.current_student.unwrap(), self.current_project.unwrap());
+            let grade: QueryResult<Grade> = g::grade
+                .filter(g::student_id
+                    .eq(student_id)
+                    .and(g::project_id.eq(project_id)))
+                .first::<Grade>(conn);
+            let grade = ChangeGrade {
+                id: match grade {
+                    Ok(x) => Some(x.id),
+                    _ => None
+                },
+                student_id: self.current_student.take(),
+                project_id: self.current_project.clone(),
+                manual_grade: self.manual_grade.take(),
+                auto_grade: self.auto_grade.take(),
+                comment: self.comment.take(),
+                compile_stdout: self.compile_stdout.take(),
+                compile_stderr: self.compile_stderr.take(),
+                compile_return: self.compile_return.take(),
+                run_stdout: self.run_stdout.take(),
+                run_stderr: self.run_stderr.take(),
+                run_return: self.run_return.take(),
+                compile_outcome: self.compile_outcome.take(),
+                run_outcome: self.run_outcome.take(),
+                image_hash: self.image_hash.take(),
+                compile_signal: self.compile_signal.take(),
+                compile_core_dumped: self.compile_core_dumped.take(),
+                compile_wall_time: self.compile_wall_time.take(),
+                compile_cpu_time: self.compile_cpu_time.take(),
+                compile_peak_memory: self.compile_peak_memory.take(),
+                run_signal: self.run_signal.take(),
+                run_core_dumped: self.run_core_dumped.take(),
+                run_wall_time: self.run_wall_time.take(),
+                run_cpu_time: self.run_cpu_time.take(),
+                run_peak_memory: self.run_peak_memory.take(),
+                max_auto_grade: self.max_auto_grade.take(),
+                submission_hash: self.submission_hash.take(),
+                template_hash: self.template_hash.take(),
+            };
+
+            diesel::replace_into(crate::schema::grade::table)
+                .values(grade)
+                .execute(conn)
+                .and_then_into(|_| g::grade
+                    .filter(g::student_id
+                        .eq(student_id)
+                        .and(g::project_id.eq(project_id)))
+                    .select(g::id)
+                    .first::<i32>(conn))
+                .and_then(|id| crate::artifact::commit(conn, id)
+                    .and(crate::testcase::commit(conn, id))
+                    .and(crate::stage::commit(conn, id))
+                    .and(crate::rubric::commit(conn, id)))
+                .and_then(|_| {
+                    self.store(conn)
+                })
+        }
+    }
+    /// Forget the compile and run results, the grades and the comment are kept
+    pub fn clean_result(&mut self) {
+        self.compile_return.take();
+        self.compile_stderr.take();
+        self.compile_stdout.take();
+        self.run_return.take();
+        self.run_stderr.take();
+        self.run_stdout.take();
+        self.compile_outcome.take();
+        self.run_outcome.take();
+        self.image_hash.take();
+        self.submission_hash.take();
+        self.template_hash.take();
+        self.compile_signal.take();
+        self.compile_core_dumped.take();
+        self.compile_wall_time.take();
+        self.compile_cpu_time.take();
+        self.compile_peak_memory.take();
+        self.run_signal.take();
+        self.run_core_dumped.take();
+        self.run_wall_time.take();
+        self.run_cpu_time.take();
+        self.run_peak_memory.take();
+    }
     pub fn get_global(conn: &diesel::SqliteConnection) -> Result<Self> {
         use crate::schema::configuration::dsl::*;
         configuration
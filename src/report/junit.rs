@@ -0,0 +1,114 @@
+use anyhow::*;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use super::TestOutcome;
+
+fn attribute(element: &BytesStart, reader: &Reader<&[u8]>, key: &[u8]) -> Result<Option<String>> {
+    for i in element.attributes() {
+        let i = i?;
+        if i.key == key {
+            return Ok(Some(i.unescape_and_decode_value(reader)?));
+        }
+    }
+    Ok(None)
+}
+
+fn test_case(element: &BytesStart, reader: &Reader<&[u8]>) -> Result<TestOutcome> {
+    let name = attribute(element, reader, b"name")?
+        .ok_or(anyhow!("test case without a name at {}", reader.buffer_position()))?;
+    let name = match attribute(element, reader, b"classname")? {
+        Some(class) if !class.is_empty() => format!("{}.{}", class, name),
+        _ => name
+    };
+    Ok(TestOutcome { name, passed: true, message: None })
+}
+
+fn is_failure(name: &[u8]) -> bool {
+    name == b"failure" || name == b"error" || name == b"skipped"
+}
+
+/// Mark `test` as failed, the message of the element comes first and its text is appended later
+fn fail(test: &mut TestOutcome, element: &BytesStart, reader: &Reader<&[u8]>) -> Result<()> {
+    test.passed = false;
+    let message = attribute(element, reader, b"message")?
+        .unwrap_or_else(|| String::from_utf8_lossy(element.name()).to_string());
+    test.message = Some(match test.message.take() {
+        Some(x) => format!("{}\n{}", x, message),
+        None => message
+    });
+    Ok(())
+}
+
+/// Test cases of a JUnit XML report, a case passes unless it has a `failure`, `error` or `skipped`
+/// child. Names are `classname.name` if the class name is given.
+pub fn parse(content: &str) -> Result<Vec<TestOutcome>> {
+    let mut reader = Reader::from_str(content);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut tests = Vec::new();
+    let mut current: Option<TestOutcome> = None;
+    // inside a failure element, its text is part of the message
+    let mut in_failure = false;
+    loop {
+        match reader.read_event(&mut buffer)
+            .map_err(|e| anyhow!("invalid JUnit XML at {}: {}", reader.buffer_position(), e))? {
+            Event::Start(ref e) if e.name() == b"testcase" => current = Some(test_case(e, &reader)?),
+            Event::Empty(ref e) if e.name() == b"testcase" => tests.push(test_case(e, &reader)?),
+            Event::End(ref e) if e.name() == b"testcase" => tests.extend(current.take()),
+            Event::Start(ref e) if is_failure(e.name()) => if let Some(test) = current.as_mut() {
+                fail(test, e, &reader)?;
+                in_failure = true;
+            },
+            Event::Empty(ref e) if is_failure(e.name()) => if let Some(test) = current.as_mut() {
+                fail(test, e, &reader)?;
+            },
+            Event::End(ref e) if is_failure(e.name()) => in_failure = false,
+            Event::Text(ref e) if in_failure => if let Some(test) = current.as_mut() {
+                let text = e.unescape_and_decode(&reader)?;
+                test.message.get_or_insert_with(String::new).push_str(&format!("\n{}", text));
+            },
+            Event::CData(ref e) if in_failure => if let Some(test) = current.as_mut() {
+                let text = reader.decode(e.escaped())?;
+                test.message.get_or_insert_with(String::new).push_str(&format!("\n{}", text));
+            },
+            Event::Eof => break,
+            _ => ()
+        }
+        buffer.clear();
+    }
+    Ok(tests)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let tests = parse(r#"<?xml version="1.0" encoding="utf-8"?>
+<testsuites>
+  <testsuite name="pytest" tests="4">
+    <testcase classname="test_calc" name="test_add" time="0.001"/>
+    <testcase classname="test_calc" name="test_div" time="0.002">
+      <failure message="ZeroDivisionError: division by zero">trace &lt;1&gt;</failure>
+    </testcase>
+    <testcase name="test_pow"><skipped message="not implemented"/></testcase>
+    <testcase name="test_mod"><system-out>fine</system-out></testcase>
+    <testcase name="test_log"><error><![CDATA[<crash>]]></error></testcase>
+  </testsuite>
+</testsuites>"#)?;
+        let summary = tests.iter()
+            .map(|x| (x.name.as_str(), x.passed, x.message.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            ("test_calc.test_add", true, None),
+            ("test_calc.test_div", false, Some("ZeroDivisionError: division by zero\ntrace <1>")),
+            ("test_pow", false, Some("not implemented")),
+            ("test_mod", true, None),
+            ("test_log", false, Some("error\n<crash>")),
+        ]);
+        assert!(parse("<testsuite><testcase></testsuite>").is_err());
+        Ok(())
+    }
+}
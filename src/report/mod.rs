@@ -0,0 +1,268 @@
+use anyhow::*;
+
+use crate::model::ChangeCaseResult;
+use crate::testcase::Verdict;
+
+pub mod junit;
+pub mod tap;
+
+/// File `run.sh` may write its results to, relative to `/project`.
+/// The absolute path is exported to every script as `HELPER_RESULT`.
+pub const RESULT_FILE: &str = ".helper-result.json";
+
+/// # Result Protocol
+/// ```json
+/// {
+///     "score": 7,
+///     "max_score": 10,
+///     "tests": [
+///         {"name": "parse", "score": 2, "max_score": 2},
+///         {"name": "eval", "score": 5, "max_score": 8, "message": "wrong precedence of `^`"}
+///     ]
+/// }
+/// ```
+/// `score` defaults to the sum of the test scores, `max_score` of a test defaults to its `score`
+/// Where the results of `run.sh` come from
+///
+/// - `json`: the JSON file at `$HELPER_RESULT`, falling back to `[RESULT] x/y` on stdout, the default
+/// - `junit:PATH`: a JUnit XML report written to `PATH`, relative to `/project`
+/// - `tap`: a TAP stream on stdout
+/// - `tap:PATH`: a TAP stream written to `PATH`, relative to `/project`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultFormat {
+    Json,
+    Junit(String),
+    Tap(Option<String>),
+}
+
+impl std::str::FromStr for ResultFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().unwrap_or("").trim(), parts.next().map(str::trim)) {
+            ("json", None) => Ok(ResultFormat::Json),
+            ("junit", Some(path)) if !path.is_empty() => Ok(ResultFormat::Junit(path.to_string())),
+            ("tap", None) => Ok(ResultFormat::Tap(None)),
+            ("tap", Some(path)) if !path.is_empty() => Ok(ResultFormat::Tap(Some(path.to_string()))),
+            _ => Err(anyhow!("invalid result format {}, expected one of json, junit:PATH, tap, tap:PATH", s))
+        }
+    }
+}
+
+impl std::fmt::Display for ResultFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultFormat::Json => write!(f, "json"),
+            ResultFormat::Junit(path) => write!(f, "junit:{}", path),
+            ResultFormat::Tap(None) => write!(f, "tap"),
+            ResultFormat::Tap(Some(path)) => write!(f, "tap:{}", path),
+        }
+    }
+}
+
+impl ResultFormat {
+    /// The file `run.sh` writes, removed before every run
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            ResultFormat::Json => Some(RESULT_FILE),
+            ResultFormat::Junit(path) | ResultFormat::Tap(Some(path)) => Some(path),
+            ResultFormat::Tap(None) => None,
+        }
+    }
+}
+
+/// Pass or fail of a single test from a JUnit or TAP report
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    pub name: String,
+    pub passed: bool,
+    pub message: Option<String>,
+}
+
+/// Points of the tests of a report as comma separated `GLOB=POINTS` pairs matched against
+/// the test names, the first match wins and unmatched tests are worth 1 point
+#[derive(Debug, Clone, Default)]
+pub struct Points(Vec<(glob::Pattern, f64)>);
+
+impl std::str::FromStr for Points {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let split = x.rfind('=').ok_or(anyhow!("expected GLOB=POINTS, got {}", x))?;
+                let pattern = glob::Pattern::new(x[..split].trim())
+                    .map_err(|e| anyhow!("invalid test glob {}: {}", x, e))?;
+                let points: f64 = x[split + 1..].trim().parse()
+                    .map_err(|e| anyhow!("invalid points {}: {}", x, e))?;
+                if points.is_finite() && points >= 0.0 {
+                    Ok((pattern, points))
+                } else {
+                    Err(anyhow!("points must not be negative: {}", x))
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Points)
+    }
+}
+
+impl std::fmt::Display for Points {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.iter()
+            .map(|(pattern, points)| format!("{}={}", pattern.as_str(), points))
+            .collect::<Vec<_>>()
+            .join(","))
+    }
+}
+
+impl Points {
+    pub fn of(&self, test: &str) -> f64 {
+        self.0.iter()
+            .find(|(pattern, _)| pattern.matches(test))
+            .map(|(_, points)| *points)
+            .unwrap_or(1.0)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct Report {
+    pub score: Option<f64>,
+    pub max_score: Option<f64>,
+    #[serde(default)]
+    pub tests: Vec<TestReport>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct TestReport {
+    pub name: String,
+    pub score: f64,
+    pub max_score: Option<f64>,
+    pub message: Option<String>,
+}
+
+impl TestReport {
+    fn max_score(&self) -> f64 {
+        self.max_score.unwrap_or(self.score)
+    }
+}
+
+impl Report {
+    pub fn parse_json(content: &str) -> Result<Self> {
+        let report: Report = serde_json::from_str(content)
+            .map_err(|e| anyhow!("invalid result file: {}", e))?;
+        if report.score.is_none() && report.tests.is_empty() {
+            return Err(anyhow!("result file has neither a score nor tests"));
+        }
+        for i in &report.tests {
+            if !i.score.is_finite() || i.score < 0.0 || i.score > i.max_score() {
+                return Err(anyhow!("invalid score {} of test {}", i.score, i.name));
+            }
+        }
+        Ok(report)
+    }
+
+    /// A report of passed and failed tests, a passed test gets its full points
+    pub fn from_outcomes(outcomes: Vec<TestOutcome>, points: &Points) -> Result<Self> {
+        if outcomes.is_empty() {
+            return Err(anyhow!("the report contains no tests"));
+        }
+        let tests = outcomes.into_iter()
+            .map(|x| {
+                let max_score = points.of(&x.name);
+                TestReport {
+                    score: if x.passed { max_score } else { 0.0 },
+                    max_score: Some(max_score),
+                    name: x.name,
+                    message: x.message,
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(Report {
+            score: None,
+            max_score: Some(tests.iter().map(TestReport::max_score).sum()),
+            tests,
+        })
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score.unwrap_or_else(|| self.tests.iter().map(|x| x.score).sum())
+    }
+
+    pub fn auto_grade(&self) -> f64 {
+        if let Some(max_score) = self.max_score.filter(|x| self.score() > *x) {
+            log::warn!("reported score {} exceeds the maximum {}", self.score(), max_score);
+        }
+        self.score()
+    }
+
+    /// Tests as case results, a test passes if it got its full score
+    pub fn cases(&self, return_code: Option<i32>) -> Vec<ChangeCaseResult> {
+        self.tests.iter()
+            .map(|x| ChangeCaseResult {
+                grade_id: None,
+                name: Some(x.name.clone()),
+                verdict: Some(if x.score >= x.max_score() { Verdict::Accepted } else { Verdict::WrongAnswer }.to_string()),
+                weight: Some(x.max_score()),
+                score: Some(x.score),
+                return_code,
+                wall_time: None,
+                stdout: Some(String::new()),
+                stderr: Some(String::new()),
+                message: x.message.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_json() -> Result<()> {
+        let report = Report::parse_json(r#"{"tests": [
+            {"name": "a", "score": 2, "max_score": 2},
+            {"name": "b", "score": 1.5, "max_score": 3, "message": "off by one"}
+        ]}"#)?;
+        assert_eq!(report.score(), 3.5);
+        assert_eq!(report.auto_grade(), 3.5);
+        let cases = report.cases(Some(0));
+        assert_eq!(cases[0].verdict.as_deref(), Some("AC"));
+        assert_eq!(cases[1].verdict.as_deref(), Some("WA"));
+        assert_eq!(cases[1].message.as_deref(), Some("off by one"));
+        assert_eq!(Report::parse_json(r#"{"score": 7, "max_score": 10}"#)?.auto_grade(), 7.0);
+        assert!(Report::parse_json("{}").is_err());
+        assert!(Report::parse_json("[RESULT] 7/10").is_err());
+        assert!(Report::parse_json(r#"{"tests": [{"name": "a", "score": 3, "max_score": 2}]}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_points() -> Result<()> {
+        let points: Points = "test_calc.*=2, test_calc.test_div=5, *_bonus=0.5".parse()?;
+        assert_eq!(points.of("test_calc.test_div"), 2.0);
+        assert_eq!(points.of("test_pow_bonus"), 0.5);
+        assert_eq!(points.of("test_pow"), 1.0);
+        assert!("a".parse::<Points>().is_err());
+        assert!("a=-1".parse::<Points>().is_err());
+        let report = Report::from_outcomes(vec![
+            TestOutcome { name: String::from("test_calc.test_add"), passed: true, message: None },
+            TestOutcome { name: String::from("test_pow"), passed: false, message: Some(String::from("skipped")) },
+        ], &points)?;
+        assert_eq!((report.score(), report.max_score), (2.0, Some(3.0)));
+        assert!(Report::from_outcomes(Vec::new(), &points).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_format() -> Result<()> {
+        assert_eq!("tap".parse::<ResultFormat>()?, ResultFormat::Tap(None));
+        assert_eq!("junit:report.xml".parse::<ResultFormat>()?.file(), Some("report.xml"));
+        assert!("junit".parse::<ResultFormat>().is_err());
+        assert!("xml:a".parse::<ResultFormat>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::*;
+
+use super::TestOutcome;
+
+/// Test points of a TAP stream. A point with a `SKIP` or `TODO` directive does not pass, the
+/// diagnostics (`# ...` lines and YAML blocks) following a point become its message.
+pub fn parse(content: &str) -> Result<Vec<TestOutcome>> {
+    let mut tests: Vec<TestOutcome> = Vec::new();
+    let mut in_yaml = false;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if in_yaml {
+            if trimmed == "..." {
+                in_yaml = false;
+            } else if let Some(test) = tests.last_mut() {
+                test.message.get_or_insert_with(String::new).push_str(&format!("{}\n", trimmed));
+            }
+            continue;
+        }
+        let (passed, rest) = if let Some(rest) = line.strip_prefix("not ok") {
+            (false, rest)
+        } else if let Some(rest) = line.strip_prefix("ok") {
+            (true, rest)
+        } else if trimmed == "---" && !tests.is_empty() {
+            in_yaml = true;
+            continue;
+        } else if let Some(comment) = trimmed.strip_prefix('#') {
+            if let Some(test) = tests.last_mut().filter(|x| !x.passed) {
+                test.message.get_or_insert_with(String::new).push_str(&format!("{}\n", comment.trim()));
+            }
+            continue;
+        } else if let Some(reason) = trimmed.strip_prefix("Bail out!") {
+            return Err(anyhow!("TAP producer bailed out:{}", reason));
+        } else {
+            continue;
+        };
+        // `ok` glued to something else, e.g. `okay`
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            continue;
+        }
+        let mut parts = rest.splitn(2, '#');
+        let description = parts.next().unwrap_or("").trim();
+        let directive = parts.next().map(str::trim);
+        let mut words = description.splitn(2, ' ');
+        let number = words.next()
+            .filter(|x| x.parse::<u32>().is_ok())
+            .map(ToString::to_string);
+        let name = match number {
+            Some(number) => words.next()
+                .map(|x| x.trim_start_matches('-').trim())
+                .filter(|x| !x.is_empty())
+                .map(ToString::to_string)
+                .unwrap_or(number),
+            None => description.trim_start_matches('-').trim().to_string(),
+        };
+        let name = if name.is_empty() { (tests.len() + 1).to_string() } else { name };
+        let skipped = directive
+            .filter(|x| x.to_uppercase().starts_with("SKIP") || x.to_uppercase().starts_with("TODO"));
+        tests.push(TestOutcome {
+            name,
+            passed: passed && skipped.is_none(),
+            message: skipped.map(ToString::to_string),
+        });
+    }
+    for i in tests.iter_mut() {
+        if let Some(x) = i.message.as_mut() {
+            x.truncate(x.trim_end().len());
+        }
+    }
+    Ok(tests)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let tests = parse("TAP version 13
+1..5
+ok 1 - parses input
+not ok 2 - evaluates precedence
+  ---
+  message: expected 7, got 9
+  ...
+ok 3 # SKIP no display
+not ok - division
+# expected 2
+# got 0
+ok
+okay this is not a test point
+")?;
+        let summary = tests.iter()
+            .map(|x| (x.name.as_str(), x.passed, x.message.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            ("parses input", true, None),
+            ("evaluates precedence", false, Some("message: expected 7, got 9")),
+            ("3", false, Some("SKIP no display")),
+            ("division", false, Some("expected 2\ngot 0")),
+            ("5", true, None),
+        ]);
+        assert!(parse("1..2\nok 1\nBail out! no compiler").is_err());
+        Ok(())
+    }
+}
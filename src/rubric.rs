@@ -0,0 +1,319 @@
+use anyhow::*;
+use diesel::prelude::*;
+use structopt as opt;
+
+use crate::model::{ChangeCriterion, ChangeDeduction, ChangeRubricSelection, Criterion, Deduction, RubricSelection};
+use crate::schema::criterion::dsl as c;
+use crate::schema::deduction::dsl as d;
+use crate::schema::rubric_selection::dsl as r;
+use crate::utils::*;
+
+/// # Rubric
+/// The manual grade of a project may be split into criteria, each worth some points. Points
+/// are only taken off a criterion through its deductions, never below zero. `judge rubric`
+/// walks through the criteria in the order they were added and computes the manual grade.
+#[derive(opt::StructOpt, Debug)]
+pub enum RubricCommand {
+    #[structopt(about = "Add a criterion to the rubric of a project")]
+    AddCriterion {
+        #[structopt(short, long, help = "The project of the rubric")]
+        project: i32,
+        #[structopt(short, long, help = "Name shown to the grader and in the dump")]
+        name: String,
+        #[structopt(long, help = "Points of the criterion without any deduction")]
+        max_points: f64,
+    },
+    #[structopt(about = "Add a deduction option to a criterion")]
+    AddDeduction {
+        #[structopt(short, long, help = "The criterion id")]
+        criterion: i32,
+        #[structopt(short, long, help = "What is wrong with the submission")]
+        description: String,
+        #[structopt(long, help = "Points taken off the criterion")]
+        points: f64,
+    },
+    #[structopt(about = "Remove a criterion and its deductions, if no grade selected them")]
+    RemoveCriterion {
+        #[structopt(short, long, help = "The id to remove")]
+        id: i32,
+    },
+    #[structopt(about = "Remove a deduction option, if no grade selected it")]
+    RemoveDeduction {
+        #[structopt(short, long, help = "The id to remove")]
+        id: i32,
+    },
+    #[structopt(about = "List the rubric of a project")]
+    List {
+        #[structopt(short, long, help = "The project of the rubric")]
+        project: i32,
+    },
+}
+
+/// Criteria of a project in order, each with its deductions
+pub fn load(conn: &SqliteConnection, project_id: i32) -> Result<Vec<(Criterion, Vec<Deduction>)>> {
+    let criteria: Vec<Criterion> = c::criterion
+        .filter(c::project_id.eq(project_id))
+        .order(c::id)
+        .load(conn)?;
+    criteria.into_iter()
+        .map(|x| d::deduction
+            .filter(d::criterion_id.eq(x.id))
+            .order(d::id)
+            .load(conn)
+            .map(|y| (x, y))
+            .map_err(Into::into))
+        .collect()
+}
+
+/// Points of a criterion after the deductions in `selected`
+fn score(criterion: &Criterion, deductions: &[Deduction], selected: &[i32]) -> f64 {
+    let deducted: f64 = deductions.iter()
+        .filter(|x| selected.contains(&x.id))
+        .map(|x| x.points)
+        .sum();
+    (criterion.max_points - deducted).max(0.0)
+}
+
+/// The manual grade given by the deductions in `selected`
+fn total(rubric: &[(Criterion, Vec<Deduction>)], selected: &[i32]) -> f64 {
+    rubric.iter().map(|(x, y)| score(x, y, selected)).sum()
+}
+
+/// Drop the selections of the current, uncommitted result
+pub fn clean(conn: &SqliteConnection) -> Result<usize> {
+    diesel::delete(r::rubric_selection.filter(r::grade_id.is_null()))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Drop the selections of a committed grade, or of all grades if `grade_id` is not given
+pub fn remove(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<usize> {
+    match grade_id {
+        Some(id) => diesel::delete(r::rubric_selection.filter(r::grade_id.eq(id))).execute(conn),
+        None => diesel::delete(r::rubric_selection.filter(r::grade_id.is_not_null())).execute(conn),
+    }.map_err(Into::into)
+}
+
+/// Attach the current selections to `grade_id`, replacing the ones it had before. Without
+/// current selections the grade keeps its own, so judging again does not lose the review.
+pub fn commit(conn: &SqliteConnection, grade_id: i32) -> Result<usize> {
+    if current(conn, None)?.is_empty() {
+        return Ok(0);
+    }
+    remove(conn, Some(grade_id))?;
+    diesel::update(r::rubric_selection.filter(r::grade_id.is_null()))
+        .set(r::grade_id.eq(grade_id))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+pub fn current(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<Vec<RubricSelection>> {
+    match grade_id {
+        Some(id) => r::rubric_selection.filter(r::grade_id.eq(id)).order(r::id).load(conn),
+        None => r::rubric_selection.filter(r::grade_id.is_null()).order(r::id).load(conn),
+    }.map_err(Into::into)
+}
+
+/// One line per graded criterion of a committed grade, e.g. `Style: 8/10 (no comments -2)`
+pub fn describe(conn: &SqliteConnection, grade_id: i32) -> Result<Vec<String>> {
+    let selections = current(conn, Some(grade_id))?;
+    let selected = selections.iter().filter_map(|x| x.deduction_id).collect::<Vec<_>>();
+    let criteria: Vec<Criterion> = c::criterion
+        .filter(c::id.eq_any(selections.iter().map(|x| x.criterion_id).collect::<Vec<_>>()))
+        .order(c::id)
+        .load(conn)?;
+    let mut lines = Vec::new();
+    for criterion in criteria {
+        let deductions: Vec<Deduction> = d::deduction
+            .filter(d::criterion_id.eq(criterion.id))
+            .order(d::id)
+            .load(conn)?;
+        let reasons = deductions.iter()
+            .filter(|x| selected.contains(&x.id))
+            .map(|x| format!("{} -{}", x.description, x.points))
+            .collect::<Vec<_>>();
+        let mut line = format!("{}: {}/{}", criterion.name, score(&criterion, &deductions, &selected),
+                               criterion.max_points);
+        if !reasons.is_empty() {
+            line.push_str(&format!(" ({})", reasons.join(", ")));
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Ask for the deductions of every criterion of the current project, starting from the current
+/// selections or the committed ones. Sets the manual grade and returns it.
+pub fn grade(conn: &SqliteConnection,
+             conf: &mut crate::model::Configuration,
+             project: &crate::model::Project) -> Result<f64> {
+    use crate::schema::grade::dsl as g;
+    let rubric = load(conn, project.id)?;
+    if rubric.is_empty() {
+        return Err(anyhow!("project {} has no rubric, see `rubric add-criterion`", project.name));
+    }
+    let mut previous = current(conn, None)?;
+    if previous.is_empty() {
+        let committed = g::grade
+            .filter(g::student_id.eq(conf.current_student.ok_or(anyhow!("please set a student first"))?)
+                .and(g::project_id.eq(project.id)))
+            .select(g::id)
+            .first::<i32>(conn)
+            .optional()?;
+        if let Some(id) = committed {
+            previous = current(conn, Some(id))?;
+        }
+    }
+    let mut selected = Vec::new();
+    for (criterion, deductions) in &rubric {
+        if deductions.is_empty() {
+            continue;
+        }
+        let items = deductions.iter()
+            .map(|x| (format!("{} (-{})", x.description, x.points),
+                      previous.iter().any(|y| y.deduction_id == Some(x.id))))
+            .collect::<Vec<_>>();
+        let chosen = dialoguer::MultiSelect::new()
+            .with_prompt(format!("{} ({} points), space toggles a deduction", criterion.name, criterion.max_points))
+            .items_checked(&items)
+            .interact()?;
+        selected.extend(chosen.into_iter().map(|x| deductions[x].id));
+    }
+    let manual_grade = total(&rubric, &selected);
+    check_grade(manual_grade, project.max_manual_grade)?;
+    clean(conn)?;
+    let mut rows = Vec::new();
+    for (criterion, deductions) in &rubric {
+        log::info!("{}: {}/{}", criterion.name, score(criterion, deductions, &selected), criterion.max_points);
+        rows.push(ChangeRubricSelection { grade_id: None, criterion_id: Some(criterion.id), deduction_id: None });
+        rows.extend(deductions.iter()
+            .filter(|x| selected.contains(&x.id))
+            .map(|x| ChangeRubricSelection {
+                grade_id: None,
+                criterion_id: Some(criterion.id),
+                deduction_id: Some(x.id),
+            }));
+    }
+    diesel::insert_into(r::rubric_selection)
+        .values(&rows)
+        .execute(conn)?;
+    conf.manual_grade.replace(manual_grade);
+    conf.store(conn)?;
+    Ok(manual_grade)
+}
+
+/// Number of selections, committed or not, of `criterion` or any of `deductions`
+fn usage(conn: &SqliteConnection, criterion: Option<i32>, deductions: &[i32]) -> Result<i64> {
+    let query = r::rubric_selection.filter(r::deduction_id.eq_any(deductions));
+    match criterion {
+        Some(id) => query.or_filter(r::criterion_id.eq(id)).count().get_result(conn),
+        None => query.count().get_result(conn),
+    }.map_err(Into::into)
+}
+
+pub fn handle(conn: &SqliteConnection, subcommand: &RubricCommand) {
+    let result = match subcommand {
+        RubricCommand::AddCriterion { project, name, max_points } => {
+            check_grade(*max_points, None)
+                .and_then_into(|_| crate::schema::project::table
+                    .find(project)
+                    .get_result::<crate::model::Project>(conn))
+                .and_then(|x| {
+                    let total = load(conn, x.id)?.iter().map(|(y, _)| y.max_points).sum::<f64>() + max_points;
+                    if let Some(max) = x.max_manual_grade.filter(|max| total > *max) {
+                        log::warn!("the rubric is worth {}, more than the maximum manual grade {}", total, max);
+                    }
+                    diesel::insert_into(c::criterion)
+                        .values(ChangeCriterion {
+                            project_id: Some(x.id),
+                            name: Some(name),
+                            max_points: Some(*max_points),
+                        })
+                        .execute(conn)
+                        .map_err(Into::into)
+                })
+        }
+        RubricCommand::AddDeduction { criterion, description, points } => {
+            check_grade(*points, None)
+                .and_then_into(|_| c::criterion.find(criterion).get_result::<Criterion>(conn))
+                .and_then_into(|x| diesel::insert_into(d::deduction)
+                    .values(ChangeDeduction {
+                        criterion_id: Some(x.id),
+                        description: Some(description),
+                        points: Some(*points),
+                    })
+                    .execute(conn))
+        }
+        RubricCommand::RemoveCriterion { id } => {
+            d::deduction
+                .filter(d::criterion_id.eq(id))
+                .select(d::id)
+                .load::<i32>(conn)
+                .map_err(Into::into)
+                .and_then(|x| match usage(conn, Some(*id), &x)? {
+                    0 => diesel::delete(d::deduction.filter(d::criterion_id.eq(id)))
+                        .execute(conn)
+                        .and_then(|y| diesel::delete(c::criterion.find(id)).execute(conn).map(|z| y + z))
+                        .map_err(Into::into),
+                    n => Err(anyhow!("criterion {} is in use by {} selection(s)", id, n))
+                })
+        }
+        RubricCommand::RemoveDeduction { id } => {
+            match usage(conn, None, &[*id]) {
+                Ok(0) => diesel::delete(d::deduction.find(id))
+                    .execute(conn)
+                    .map_err(Into::into),
+                Ok(n) => Err(anyhow!("deduction {} is in use by {} selection(s)", id, n)),
+                Err(e) => Err(e)
+            }
+        }
+        RubricCommand::List { project } => {
+            load(conn, *project).map(|rubric| {
+                use prettytable::*;
+                let mut table = Table::new();
+                table.add_row(Row::new(vec![Cell::new("criterion"), Cell::new("deduction"),
+                                            Cell::new("name"), Cell::new("points")]));
+                for (criterion, deductions) in &rubric {
+                    table.add_row(Row::new(vec![Cell::new(&criterion.id.to_string()), Cell::new(""),
+                                                Cell::new(&criterion.name),
+                                                Cell::new(&criterion.max_points.to_string())]));
+                    for i in deductions {
+                        table.add_row(Row::new(vec![Cell::new(""), Cell::new(&i.id.to_string()),
+                                                    Cell::new(&i.description),
+                                                    Cell::new(&format!("-{}", i.points))]));
+                    }
+                }
+                table.printstd();
+                0
+            })
+        }
+    };
+    match result {
+        Ok(0) => (),
+        Ok(x) => log::info!("updated {} item(s)", x),
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_total() {
+        let criterion = |id, max_points| Criterion { id, project_id: 1, name: String::new(), max_points };
+        let deduction = |id, criterion_id, points| Deduction { id, criterion_id, description: String::new(), points };
+        let rubric = vec![
+            (criterion(1, 10.0), vec![deduction(1, 1, 2.0), deduction(2, 1, 5.0)]),
+            (criterion(2, 5.0), vec![deduction(3, 2, 8.0)]),
+        ];
+        assert_eq!(total(&rubric, &[]), 15.0);
+        assert_eq!(total(&rubric, &[1]), 13.0);
+        assert_eq!(total(&rubric, &[1, 2]), 8.0);
+        // a criterion never goes below zero
+        assert_eq!(total(&rubric, &[1, 3]), 8.0);
+    }
+}
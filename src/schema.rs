@@ -1,10 +1,35 @@
+table! {
+    artifact (id) {
+        id -> Integer,
+        grade_id -> Nullable<Integer>,
+        path -> Text,
+        location -> Text,
+    }
+}
+
+table! {
+    case_result (id) {
+        id -> Integer,
+        grade_id -> Nullable<Integer>,
+        name -> Text,
+        verdict -> Text,
+        weight -> Double,
+        score -> Double,
+        return_code -> Nullable<Integer>,
+        wall_time -> Nullable<Integer>,
+        stdout -> Text,
+        stderr -> Text,
+        message -> Nullable<Text>,
+    }
+}
+
 table! {
     configuration (id) {
         id -> Integer,
         current_student -> Nullable<Integer>,
         current_project -> Nullable<Integer>,
-        auto_grade -> Nullable<Integer>,
-        manual_grade -> Nullable<Integer>,
+        auto_grade -> Nullable<Double>,
+        manual_grade -> Nullable<Double>,
         comment -> Nullable<Text>,
         base_image -> Text,
         compile_stdout -> Nullable<Text>,
@@ -13,6 +38,40 @@ table! {
         run_stdout -> Nullable<Text>,
         run_stderr -> Nullable<Text>,
         run_return -> Nullable<Integer>,
+        compile_outcome -> Nullable<Text>,
+        run_outcome -> Nullable<Text>,
+        image_hash -> Nullable<Text>,
+        compile_signal -> Nullable<Integer>,
+        compile_core_dumped -> Nullable<Bool>,
+        compile_wall_time -> Nullable<Integer>,
+        compile_cpu_time -> Nullable<Integer>,
+        compile_peak_memory -> Nullable<Integer>,
+        run_signal -> Nullable<Integer>,
+        run_core_dumped -> Nullable<Bool>,
+        run_wall_time -> Nullable<Integer>,
+        run_cpu_time -> Nullable<Integer>,
+        run_peak_memory -> Nullable<Integer>,
+        max_auto_grade -> Nullable<Double>,
+        submission_hash -> Nullable<Text>,
+        template_hash -> Nullable<Text>,
+    }
+}
+
+table! {
+    criterion (id) {
+        id -> Integer,
+        project_id -> Integer,
+        name -> Text,
+        max_points -> Double,
+    }
+}
+
+table! {
+    deduction (id) {
+        id -> Integer,
+        criterion_id -> Integer,
+        description -> Text,
+        points -> Double,
     }
 }
 
@@ -21,8 +80,8 @@ table! {
         id -> Integer,
         student_id -> Integer,
         project_id -> Integer,
-        manual_grade -> Integer,
-        auto_grade -> Integer,
+        manual_grade -> Double,
+        auto_grade -> Double,
         comment -> Text,
         compile_stdout -> Text,
         compile_stderr -> Text,
@@ -30,6 +89,31 @@ table! {
         run_stdout -> Text,
         run_stderr -> Text,
         run_return -> Integer,
+        compile_outcome -> Text,
+        run_outcome -> Text,
+        image_hash -> Nullable<Text>,
+        compile_signal -> Nullable<Integer>,
+        compile_core_dumped -> Nullable<Bool>,
+        compile_wall_time -> Nullable<Integer>,
+        compile_cpu_time -> Nullable<Integer>,
+        compile_peak_memory -> Nullable<Integer>,
+        run_signal -> Nullable<Integer>,
+        run_core_dumped -> Nullable<Bool>,
+        run_wall_time -> Nullable<Integer>,
+        run_cpu_time -> Nullable<Integer>,
+        run_peak_memory -> Nullable<Integer>,
+        max_auto_grade -> Nullable<Double>,
+        submission_hash -> Nullable<Text>,
+        template_hash -> Nullable<Text>,
+    }
+}
+
+table! {
+    image (id) {
+        id -> Integer,
+        path -> Text,
+        format -> Text,
+        hash -> Text,
     }
 }
 
@@ -38,6 +122,59 @@ table! {
         id -> Integer,
         path -> Text,
         name -> Text,
+        backend -> Nullable<Text>,
+        build_time_limit -> Nullable<Integer>,
+        run_time_limit -> Nullable<Integer>,
+        build_cpu_limit -> Nullable<Integer>,
+        run_cpu_limit -> Nullable<Integer>,
+        memory_limit -> Nullable<Integer>,
+        pids_limit -> Nullable<Integer>,
+        disk_limit -> Nullable<Integer>,
+        display -> Nullable<Text>,
+        image -> Nullable<Text>,
+        submission_mount -> Nullable<Text>,
+        submission_root -> Nullable<Text>,
+        artifacts -> Nullable<Text>,
+        comparator -> Nullable<Text>,
+        result_format -> Nullable<Text>,
+        test_points -> Nullable<Text>,
+        max_auto_grade -> Nullable<Double>,
+        max_manual_grade -> Nullable<Double>,
+    }
+}
+
+table! {
+    rubric_selection (id) {
+        id -> Integer,
+        grade_id -> Nullable<Integer>,
+        criterion_id -> Integer,
+        deduction_id -> Nullable<Integer>,
+    }
+}
+
+table! {
+    snippet (id) {
+        id -> Integer,
+        project_id -> Integer,
+        key -> Text,
+        body -> Text,
+        uses -> Integer,
+    }
+}
+
+table! {
+    stage_result (id) {
+        id -> Integer,
+        grade_id -> Nullable<Integer>,
+        position -> Integer,
+        name -> Text,
+        outcome -> Text,
+        return_code -> Nullable<Integer>,
+        wall_time -> Nullable<Integer>,
+        stdout -> Text,
+        stderr -> Text,
+        score -> Nullable<Double>,
+        max_score -> Nullable<Double>,
     }
 }
 
@@ -49,8 +186,16 @@ table! {
 }
 
 allow_tables_to_appear_in_same_query!(
+    artifact,
+    case_result,
     configuration,
+    criterion,
+    deduction,
     grade,
+    image,
     project,
+    rubric_selection,
+    snippet,
+    stage_result,
     student,
 );
@@ -0,0 +1,221 @@
+use std::path::Path;
+
+use anyhow::*;
+use diesel::prelude::*;
+use structopt as opt;
+
+use crate::model::{ChangeSnippet, Snippet};
+use crate::schema::snippet::dsl as s;
+use crate::utils::*;
+
+/// # Comment Snippets
+/// Canned comments of a project, put into the comment by `judge comment --snippet KEY` or by a
+/// line `@KEY` in the editor. The placeholders below are filled in from the current result:
+/// - `{student}`         name of the student directory
+/// - `{project}`         name of the project
+/// - `{auto_grade}`, `{max_auto_grade}`, `{manual_grade}`, `{max_manual_grade}`
+/// - `{failed_tests}`    comma separated cases or tests that were not accepted
+#[derive(opt::StructOpt, Debug)]
+pub enum SnippetCommand {
+    #[structopt(about = "Add a snippet to the comment bank of a project")]
+    Add {
+        #[structopt(short, long, help = "The project of the snippet")]
+        project: i32,
+        #[structopt(short, long, help = "Short key to insert the snippet with")]
+        key: String,
+        #[structopt(short, long, help = "Text of the snippet, written in the editor if not given")]
+        body: Option<String>,
+        #[structopt(long, short, help = "Set the editor to use", env = "EDITOR", default_value = "nano")]
+        editor: String,
+    },
+    #[structopt(about = "Remove a snippet")]
+    Remove {
+        #[structopt(short, long, help = "The id to remove")]
+        id: i32,
+    },
+    #[structopt(about = "List the snippets of a project and how often they were used")]
+    List {
+        #[structopt(short, long, help = "The project of the snippets")]
+        project: i32,
+    },
+}
+
+pub const PLACEHOLDERS: &[&str] = &[
+    "student", "project", "auto_grade", "max_auto_grade", "manual_grade", "max_manual_grade", "failed_tests",
+];
+
+/// Names of the `{name}` placeholders in `body`, braces around anything else are plain text
+fn placeholders(body: &str) -> Vec<&str> {
+    body.split('{')
+        .skip(1)
+        .filter_map(|x| x.split('}').next().filter(|_| x.contains('}')))
+        .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+        .collect()
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if !key.is_empty() && key.chars().all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-') {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid snippet key {:?}, use letters, digits, _ and -", key))
+    }
+}
+
+fn check_body(body: &str) -> Result<()> {
+    match placeholders(body).into_iter().find(|x| !PLACEHOLDERS.contains(x)) {
+        Some(x) => Err(anyhow!("unknown placeholder {{{}}}, expected one of {}", x, PLACEHOLDERS.join(", "))),
+        None if body.trim().is_empty() => Err(anyhow!("empty snippet")),
+        None => Ok(())
+    }
+}
+
+/// Fill in the placeholders of `body` with `values`
+fn render(body: &str, values: &[(&str, String)]) -> String {
+    values.iter().fold(body.to_string(), |x, (key, value)| x.replace(&format!("{{{}}}", key), value))
+}
+
+/// Replace every line `@KEY` of `text` by `lookup(KEY)`, unknown keys are kept as they are
+fn expand(text: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
+    text.lines()
+        .map(|line| line.trim()
+            .strip_prefix('@')
+            .and_then(&mut lookup)
+            .unwrap_or_else(|| line.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Placeholder values of the current result
+fn values(conn: &SqliteConnection, conf: &crate::model::Configuration, project: &crate::model::Project)
+          -> Result<Vec<(&'static str, String)>> {
+    let student: crate::model::Student = crate::schema::student::table
+        .find(conf.current_student.ok_or(anyhow!("please set a student first"))?)
+        .get_result(conn)?;
+    let optional = |x: Option<f64>| x.map(|x| x.to_string()).unwrap_or_default();
+    let failed = crate::testcase::current(conn, None)?
+        .into_iter()
+        .filter(|x| x.verdict != crate::testcase::Verdict::Accepted.to_string())
+        .map(|x| x.name)
+        .collect::<Vec<_>>();
+    Ok(vec![
+        ("student", Path::new(&student.path)
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or(student.path)),
+        ("project", project.name.clone()),
+        ("auto_grade", optional(conf.auto_grade)),
+        ("max_auto_grade", optional(project.max_auto_grade.or(conf.max_auto_grade))),
+        ("manual_grade", optional(conf.manual_grade)),
+        ("max_manual_grade", optional(project.max_manual_grade)),
+        ("failed_tests", failed.join(", ")),
+    ])
+}
+
+/// Snippets of a project by key, each rendered for the current result and counted as used
+pub struct Bank<'a> {
+    conn: &'a SqliteConnection,
+    snippets: Vec<Snippet>,
+    values: Vec<(&'static str, String)>,
+}
+
+impl<'a> Bank<'a> {
+    pub fn new(conn: &'a SqliteConnection,
+               conf: &crate::model::Configuration,
+               project: &crate::model::Project) -> Result<Self> {
+        Ok(Bank {
+            conn,
+            snippets: s::snippet.filter(s::project_id.eq(project.id)).order(s::key).load(conn)?,
+            values: values(conn, conf, project)?,
+        })
+    }
+    pub fn keys(&self) -> Vec<&str> {
+        self.snippets.iter().map(|x| x.key.as_str()).collect()
+    }
+    /// The rendered snippet `key`, `None` if the project has no such snippet
+    pub fn take(&self, key: &str) -> Result<Option<String>> {
+        match self.snippets.iter().find(|x| x.key == key) {
+            Some(x) => diesel::update(s::snippet.find(x.id))
+                .set(s::uses.eq(s::uses + 1))
+                .execute(self.conn)
+                .map(|_| Some(render(&x.body, &self.values)))
+                .map_err(Into::into),
+            None => Ok(None)
+        }
+    }
+    /// `text` with the `@KEY` lines replaced
+    pub fn expand(&self, text: &str) -> String {
+        expand(text, |key| match self.take(key) {
+            Ok(Some(x)) => Some(x),
+            Ok(None) => {
+                log::warn!("no snippet {}, the line is kept", key);
+                None
+            }
+            Err(e) => {
+                log::error!("failed to insert snippet {}: {}", key, e);
+                None
+            }
+        })
+    }
+}
+
+pub fn handle(conn: &SqliteConnection, subcommand: &SnippetCommand) {
+    match subcommand {
+        SnippetCommand::Add { project, key, body, editor } => {
+            check_key(key)
+                .and_then(|_| match body {
+                    Some(x) => Ok(x.clone()),
+                    None => dialoguer::Editor::new()
+                        .executable(editor)
+                        .edit("")?
+                        .ok_or(anyhow!("snippet not saved"))
+                })
+                .and_then(|body| check_body(&body).map(|_| body))
+                .and_then_into(|body| crate::schema::project::table
+                    .find(project)
+                    .get_result::<crate::model::Project>(conn)
+                    .map(|_| body))
+                .and_then_into(|body| diesel::insert_into(s::snippet)
+                    .values(ChangeSnippet {
+                        project_id: Some(*project),
+                        key: Some(key),
+                        body: Some(body.trim_end()),
+                    })
+                    .execute(conn))
+                .map(|x| log::info!("updated {} item(s)", x))
+                .unwrap_with_log();
+        }
+        SnippetCommand::Remove { id } => {
+            diesel::delete(s::snippet.find(id))
+                .execute(conn)
+                .map(|x| log::info!("updated {} item(s)", x))
+                .unwrap_with_log();
+        }
+        SnippetCommand::List { project } => {
+            let snippets = s::snippet
+                .filter(s::project_id.eq(project))
+                .order(s::key)
+                .load::<Snippet>(conn)
+                .unwrap_with_log();
+            println!("{}", tablefy::into_string(&snippets));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() -> Result<()> {
+        assert_eq!(placeholders("{student}: {failed_tests} {x y} {} {Student"), vec!["student", "failed_tests"]);
+        assert!(check_body("Hi {student}, see {failed_tests}").is_ok());
+        assert!(check_body("fn main() { {student} }").is_ok());
+        assert!(check_body("{grade}").is_err());
+        assert!(check_body(" ").is_err());
+        let values = [("student", String::from("alice")), ("failed_tests", String::from("a, b"))];
+        assert_eq!(render("{student} failed {failed_tests}, {student}!", &values), "alice failed a, b, alice!");
+        let text = expand("Good job.\n  @null\n@unknown", |x| Some(String::from("Check null")).filter(|_| x == "null"));
+        assert_eq!(text, "Good job.\nCheck null\n@unknown");
+        Ok(())
+    }
+}
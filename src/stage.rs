@@ -0,0 +1,49 @@
+use anyhow::*;
+use diesel::prelude::*;
+
+use crate::model::{ChangeStageResult, StageResult};
+use crate::schema::stage_result::dsl as r;
+
+/// Drop the stage results of the current, uncommitted result
+pub fn clean(conn: &SqliteConnection) -> Result<usize> {
+    diesel::delete(r::stage_result.filter(r::grade_id.is_null()))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Drop the stage results of a committed grade, or of all grades if `grade_id` is not given
+pub fn remove(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<usize> {
+    match grade_id {
+        Some(id) => diesel::delete(r::stage_result.filter(r::grade_id.eq(id))).execute(conn),
+        None => diesel::delete(r::stage_result.filter(r::grade_id.is_not_null())).execute(conn),
+    }.map_err(Into::into)
+}
+
+/// Attach the current stage results to `grade_id`, replacing the ones it had before
+pub fn commit(conn: &SqliteConnection, grade_id: i32) -> Result<usize> {
+    remove(conn, Some(grade_id))?;
+    diesel::update(r::stage_result.filter(r::grade_id.is_null()))
+        .set(r::grade_id.eq(grade_id))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Replace the current stage results
+pub fn record(conn: &SqliteConnection, results: &[ChangeStageResult]) -> Result<usize> {
+    clean(conn)?;
+    if results.is_empty() {
+        return Ok(0);
+    }
+    diesel::insert_into(r::stage_result)
+        .values(results)
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Stage results in pipeline order
+pub fn current(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<Vec<StageResult>> {
+    match grade_id {
+        Some(id) => r::stage_result.filter(r::grade_id.eq(id)).order(r::position).load(conn),
+        None => r::stage_result.filter(r::grade_id.is_null()).order(r::position).load(conn),
+    }.map_err(Into::into)
+}
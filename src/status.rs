@@ -18,6 +18,25 @@ pub enum StatusCommand {
     CurrentRunStdout,
     #[structopt(about = "Check run stderr")]
     CurrentRunStderr,
+    #[structopt(about = "List captured artifacts")]
+    Artifacts {
+        #[structopt(short, long, help = "Artifacts of a committed grade instead of the current result")]
+        grade_id: Option<i32>,
+    },
+    #[structopt(about = "List test case verdicts")]
+    Cases {
+        #[structopt(short, long, help = "Cases of a committed grade instead of the current result")]
+        grade_id: Option<i32>,
+        #[structopt(short, long, help = "Show stdout and stderr of this case")]
+        name: Option<String>,
+    },
+    #[structopt(about = "List the stages of the pipeline")]
+    Stages {
+        #[structopt(short, long, help = "Stages of a committed grade instead of the current result")]
+        grade_id: Option<i32>,
+        #[structopt(short, long, help = "Show stdout and stderr of this stage")]
+        name: Option<String>,
+    },
     #[structopt(about = "List all project template(s)")]
     Projects,
     #[structopt(about = "List all students")]
@@ -33,6 +52,26 @@ pub enum StatusCommand {
     },
 }
 
+fn optional<T: ToString>(x: &Option<T>) -> String {
+    x.as_ref().map(ToString::to_string).unwrap_or_else(String::new)
+}
+
+/// signal, core dump, times and peak memory of a stage as table rows
+fn usage_rows(table: &mut prettytable::Table, stage: &str, signal: &Option<i32>, core_dumped: &Option<bool>,
+              wall_time: &Option<i32>, cpu_time: &Option<i32>, peak_memory: &Option<i32>) {
+    use prettytable::*;
+    let rows = [
+        ("signal", optional(signal)),
+        ("core_dumped", optional(core_dumped)),
+        ("wall_time", wall_time.map(|x| format!("{:.3}s", x as f64 / 1000.0)).unwrap_or_else(String::new)),
+        ("cpu_time", cpu_time.map(|x| format!("{:.3}s", x as f64 / 1000.0)).unwrap_or_else(String::new)),
+        ("peak_memory", peak_memory.map(|x| format!("{} KiB", x)).unwrap_or_else(String::new)),
+    ];
+    for (name, value) in rows.iter() {
+        table.add_row(Row::new(vec![Cell::new(&format!("{}_{}", stage, name)), Cell::new(value)]));
+    }
+}
+
 pub fn handle(subcommand: &StatusCommand, conn: &SqliteConnection) {
     match subcommand {
         StatusCommand::Current => {
@@ -46,33 +85,132 @@ pub fn handle(subcommand: &StatusCommand, conn: &SqliteConnection) {
                     table.add_row(Row::new(vec![Cell::new("current project"),
                                                 Cell::new(&x.current_project.as_ref().map(|x| x.to_string())
                                                     .unwrap_or_else(String::new))]));
+                    let project: Option<model::Project> = x.current_project
+                        .and_then(|id| schema::project::table.find(id).first(conn).ok());
+                    let max_auto = project.as_ref().and_then(|p| p.max_auto_grade).or(x.max_auto_grade);
+                    let max_manual = project.as_ref().and_then(|p| p.max_manual_grade);
                     table.add_row(Row::new(vec![Cell::new("auto_grade"),
-                                                Cell::new(&x.auto_grade.as_ref().map(|x| x.to_string())
+                                                Cell::new(&x.auto_grade.map(|x| format_grade(x, max_auto))
                                                     .unwrap_or_else(String::new))]));
                     table.add_row(Row::new(vec![Cell::new("manual_grade"),
-                                                Cell::new(&x.manual_grade.as_ref().map(|x| x.to_string())
+                                                Cell::new(&x.manual_grade.map(|x| format_grade(x, max_manual))
                                                     .unwrap_or_else(String::new))]));
                     table.add_row(Row::new(vec![Cell::new("comment"),
                                                 Cell::new(x.comment.as_ref().map(AsRef::as_ref)
                                                     .unwrap_or(""))]));
                     table.add_row(Row::new(vec![Cell::new("base_image"),
                                                 Cell::new(x.base_image.as_ref())]));
+                    table.add_row(Row::new(vec![Cell::new("image_hash"),
+                                                Cell::new(x.image_hash.as_ref().map(AsRef::as_ref)
+                                                    .unwrap_or(""))]));
+                    table.add_row(Row::new(vec![Cell::new("submission_hash"),
+                                                Cell::new(x.submission_hash.as_ref().map(AsRef::as_ref)
+                                                    .unwrap_or(""))]));
+                    table.add_row(Row::new(vec![Cell::new("template_hash"),
+                                                Cell::new(x.template_hash.as_ref().map(AsRef::as_ref)
+                                                    .unwrap_or(""))]));
                     table.add_row(Row::new(vec![Cell::new("compile_return"),
                                                 Cell::new(&x.compile_return.as_ref().map(|x| x.to_string())
                                                     .unwrap_or_else(String::new))]));
+                    table.add_row(Row::new(vec![Cell::new("compile_outcome"),
+                                                Cell::new(x.compile_outcome.as_ref().map(AsRef::as_ref)
+                                                    .unwrap_or(""))]));
+                    usage_rows(&mut table, "compile", &x.compile_signal, &x.compile_core_dumped,
+                               &x.compile_wall_time, &x.compile_cpu_time, &x.compile_peak_memory);
                     table.add_row(Row::new(vec![Cell::new("run_return"),
                                                 Cell::new(&x.run_return.as_ref().map(|x| x.to_string())
                                                     .unwrap_or_else(String::new))]));
+                    table.add_row(Row::new(vec![Cell::new("run_outcome"),
+                                                Cell::new(x.run_outcome.as_ref().map(AsRef::as_ref)
+                                                    .unwrap_or(""))]));
+                    usage_rows(&mut table, "run", &x.run_signal, &x.run_core_dumped,
+                               &x.run_wall_time, &x.run_cpu_time, &x.run_peak_memory);
                     table.printstd();
                 })
                 .unwrap_with_log();
         }
         StatusCommand::Projects => {
-            let projects = schema::project::table
+            let mut projects = schema::project::table
                 .load::<model::Project>(conn)
                 .unwrap_with_log();
+            if let Ok(conf) = model::Configuration::get_global(conn) {
+                for i in projects.iter_mut().filter(|x| x.image.is_none()) {
+                    i.image.replace(format!("{} (base)", conf.base_image));
+                }
+            }
             println!("{}", tablefy::into_string(&projects));
         }
+        StatusCommand::Artifacts { grade_id } => {
+            use schema::artifact::dsl as a;
+            let artifacts = match grade_id {
+                Some(id) => a::artifact.filter(a::grade_id.eq(id)).load::<model::Artifact>(conn),
+                None => a::artifact.filter(a::grade_id.is_null()).load::<model::Artifact>(conn),
+            }.unwrap_with_log();
+            println!("{}", tablefy::into_string(&artifacts));
+        }
+        StatusCommand::Cases { grade_id, name } => {
+            let cases = crate::testcase::current(conn, *grade_id)
+                .unwrap_with_log();
+            match name {
+                Some(name) => {
+                    let case = cases.iter()
+                        .find(|x| &x.name == name)
+                        .ok_or(anyhow::anyhow!("no result for case {}", name))
+                        .unwrap_with_log();
+                    if let Some(message) = &case.message {
+                        println!("Message:\n{}", message);
+                    }
+                    println!("Stdout:\n{}", case.stdout);
+                    println!("Stderr:\n{}", case.stderr);
+                }
+                None => {
+                    let mut table = prettytable::Table::new();
+                    table.add_row(prettytable::Row::new(vec![Cell::new("case"), Cell::new("verdict"),
+                                                             Cell::new("score"), Cell::new("return code"),
+                                                             Cell::new("wall time")]));
+                    for i in &cases {
+                        table.add_row(prettytable::Row::new(vec![
+                            Cell::new(&i.name),
+                            Cell::new(&i.verdict),
+                            Cell::new(&format!("{}/{}", i.score, i.weight)),
+                            Cell::new(&optional(&i.return_code)),
+                            Cell::new(&optional(&i.wall_time.map(|x| format!("{} ms", x)))),
+                        ]));
+                    }
+                    table.printstd();
+                }
+            }
+        }
+        StatusCommand::Stages { grade_id, name } => {
+            let stages = crate::stage::current(conn, *grade_id)
+                .unwrap_with_log();
+            match name {
+                Some(name) => {
+                    let stage = stages.iter()
+                        .find(|x| &x.name == name)
+                        .ok_or(anyhow::anyhow!("no result for stage {}", name))
+                        .unwrap_with_log();
+                    println!("Stdout:\n{}", stage.stdout);
+                    println!("Stderr:\n{}", stage.stderr);
+                }
+                None => {
+                    let mut table = prettytable::Table::new();
+                    table.add_row(prettytable::Row::new(vec![Cell::new("stage"), Cell::new("outcome"),
+                                                             Cell::new("score"), Cell::new("return code"),
+                                                             Cell::new("wall time")]));
+                    for i in &stages {
+                        table.add_row(prettytable::Row::new(vec![
+                            Cell::new(&i.name),
+                            Cell::new(&i.outcome),
+                            Cell::new(&i.score.map(|x| format_grade(x, i.max_score)).unwrap_or_default()),
+                            Cell::new(&optional(&i.return_code)),
+                            Cell::new(&optional(&i.wall_time.map(|x| format!("{} ms", x)))),
+                        ]));
+                    }
+                    table.printstd();
+                }
+            }
+        }
         StatusCommand::Students => {
             let students = schema::student::table
                 .load::<model::Student>(conn)
@@ -108,8 +246,9 @@ pub fn handle(subcommand: &StatusCommand, conn: &SqliteConnection) {
                         .first::<model::Grade>(conn);
                     match grade {
                         Ok(g) => {
-                            row.add_cell(prettytable::Cell::new(&g.auto_grade.to_string()));
-                            row.add_cell(prettytable::Cell::new(&g.manual_grade.to_string()));
+                            row.add_cell(prettytable::Cell::new(
+                                &format_grade(g.auto_grade, j.max_auto_grade.or(g.max_auto_grade))));
+                            row.add_cell(prettytable::Cell::new(&format_grade(g.manual_grade, j.max_manual_grade)));
                         }
                         _ => {
                             row.add_cell(prettytable::Cell::default());
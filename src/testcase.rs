@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use diesel::prelude::*;
+
+use crate::model::{CaseResult, ChangeCaseResult};
+use crate::schema::case_result::dsl as c;
+
+/// # Test Case Layout
+/// project-name
+/// - tests/
+///   - case-name/
+///     - input     fed to run.sh on stdin, optional
+///     - expected  expected stdout checked by the comparator of the project, optional:
+///                 without it and without a checker every clean exit is accepted
+///     - weight    points of the case, 1 if missing
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub name: String,
+    /// directory of the case on the host
+    pub dir: PathBuf,
+    pub weight: f64,
+}
+
+impl Case {
+    pub fn input(&self) -> Option<PathBuf> {
+        Some(self.dir.join("input")).filter(|x| x.is_file())
+    }
+    pub fn expected(&self) -> Result<Option<String>> {
+        let path = self.dir.join("expected");
+        if path.is_file() {
+            Ok(Some(std::fs::read_to_string(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+    /// the case directory relative to `/project`, the working directory of the scripts
+    pub fn container_dir(&self) -> String {
+        format!("tests/{}", self.name)
+    }
+}
+
+/// Cases of a project template sorted by name, empty if it has no `tests` directory
+pub fn load(project: &Path) -> Result<Vec<Case>> {
+    let tests = project.join("tests");
+    if !tests.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut cases = Vec::new();
+    for entry in std::fs::read_dir(&tests)? {
+        let dir = entry?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        let name = dir.file_name()
+            .and_then(|x| x.to_str())
+            .ok_or(anyhow!("invalid case name {}", dir.display()))?
+            .to_string();
+        let weight_file = dir.join("weight");
+        let weight: f64 = if weight_file.is_file() {
+            std::fs::read_to_string(&weight_file)?
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("invalid weight of case {}: {}", name, e))?
+        } else {
+            1.0
+        };
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(anyhow!("invalid weight of case {}: {}", name, weight));
+        }
+        cases.push(Case { name, dir, weight });
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Accepted,
+    WrongAnswer,
+    /// wall clock or CPU time limit exceeded
+    TimeLimit,
+    /// non-zero exit, signal, or any other quota
+    RuntimeError,
+    MemoryLimit,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Verdict::Accepted => "AC",
+            Verdict::WrongAnswer => "WA",
+            Verdict::TimeLimit => "TLE",
+            Verdict::RuntimeError => "RE",
+            Verdict::MemoryLimit => "MLE",
+        })
+    }
+}
+
+/// Drop the case results of the current, uncommitted result
+pub fn clean(conn: &SqliteConnection) -> Result<usize> {
+    diesel::delete(c::case_result.filter(c::grade_id.is_null()))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Drop the case results of a committed grade, or of all grades if `grade_id` is not given
+pub fn remove(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<usize> {
+    match grade_id {
+        Some(id) => diesel::delete(c::case_result.filter(c::grade_id.eq(id))).execute(conn),
+        None => diesel::delete(c::case_result.filter(c::grade_id.is_not_null())).execute(conn),
+    }.map_err(Into::into)
+}
+
+/// Attach the current case results to `grade_id`, replacing the ones it had before
+pub fn commit(conn: &SqliteConnection, grade_id: i32) -> Result<usize> {
+    remove(conn, Some(grade_id))?;
+    diesel::update(c::case_result.filter(c::grade_id.is_null()))
+        .set(c::grade_id.eq(grade_id))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Replace the current case results
+pub fn record(conn: &SqliteConnection, results: &[ChangeCaseResult]) -> Result<usize> {
+    clean(conn)?;
+    if results.is_empty() {
+        return Ok(0);
+    }
+    diesel::insert_into(c::case_result)
+        .values(results)
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+pub fn current(conn: &SqliteConnection, grade_id: Option<i32>) -> Result<Vec<CaseResult>> {
+    match grade_id {
+        Some(id) => c::case_result.filter(c::grade_id.eq(id)).order(c::name).load(conn),
+        None => c::case_result.filter(c::grade_id.is_null()).order(c::name).load(conn),
+    }.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load() -> Result<()> {
+        let project = tempfile::TempDir::new()?;
+        assert!(load(project.path())?.is_empty());
+        std::fs::create_dir_all(project.path().join("tests/b"))?;
+        std::fs::create_dir_all(project.path().join("tests/a"))?;
+        std::fs::write(project.path().join("tests/b/weight"), "3\n")?;
+        std::fs::write(project.path().join("tests/README"), "not a case")?;
+        let cases = load(project.path())?;
+        assert_eq!(cases.iter().map(|x| (x.name.as_str(), x.weight)).collect::<Vec<_>>(), vec![("a", 1.0), ("b", 3.0)]);
+        assert_eq!(cases[1].container_dir(), "tests/b");
+        std::fs::write(project.path().join("tests/a/weight"), "many")?;
+        assert!(load(project.path()).is_err());
+        Ok(())
+    }
+}
@@ -28,4 +28,23 @@ impl<T, E: Display> UnwrapWithLog<T> for std::result::Result<T, E> {
             }
         }
     }
-}
\ No newline at end of file
+}
+/// A grade with its maximum and percentage if known, e.g. `7.5 / 10 (75%)`
+pub fn format_grade(grade: f64, max: Option<f64>) -> String {
+    match max {
+        Some(max) if max > 0.0 => format!("{} / {} ({}%)", grade, max, (grade / max * 1000.0).round() / 10.0),
+        Some(max) => format!("{} / {}", grade, max),
+        None => grade.to_string()
+    }
+}
+
+/// Reject grades that are negative, not finite or above `max`
+pub fn check_grade(grade: f64, max: Option<f64>) -> anyhow::Result<()> {
+    if !grade.is_finite() || grade < 0.0 {
+        Err(anyhow::anyhow!("invalid grade {}", grade))
+    } else if let Some(max) = max.filter(|x| grade > *x) {
+        Err(anyhow::anyhow!("grade {} exceeds the maximum {}", grade, max))
+    } else {
+        Ok(())
+    }
+}